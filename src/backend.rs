@@ -0,0 +1,188 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        atomic::{AtomicI32, Ordering},
+    },
+    time::Instant,
+};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
+
+use rtsc::channel_async::{Receiver, Sender};
+
+use crate::limits::Permit;
+use crate::lockout::Lockout;
+use crate::pool::{PoolHandle, PoolStatus};
+use crate::{Capabilities, Conversation, Message, Priority, Result};
+
+#[cfg(feature = "async")]
+pub type BackendFuture<'a> = Pin<Box<dyn Future<Output = Result<Conversation>> + Send + 'a>>;
+
+/// An authentication engine, which produces conversations for [`crate::Authenticator`]. The
+/// default one is the libpam worker pool, custom backends (remote agents, mocks etc.) can be used
+/// with [`crate::Authenticator::from_backend`] or [`crate::AuthenticatorBuilder::build_with`].
+///
+/// Concurrency limits and lockout checks are performed by the authenticator before a request is
+/// passed to a backend. Backends should create conversations with [`Request::conversation`] and
+/// send the outcomes with [`ConversationPeer::send_outcome`], which counts lockout failures and
+/// delays failed outcomes up to the minimum failure duration. Conversations created with
+/// [`Conversation::pair`] are relayed by the authenticator with an extra thread if lockout or a
+/// minimum failure duration is configured.
+pub trait Backend: Send + Sync {
+    fn chat_sync(&self, request: Request) -> Result<Conversation>;
+    /// The default implementation calls [`Backend::chat_sync`], backends which may block should
+    /// override it
+    #[cfg(feature = "async")]
+    fn chat(&self, request: Request) -> BackendFuture<'_> {
+        Box::pin(std::future::ready(self.chat_sync(request)))
+    }
+}
+
+/// A conversation request. The request holds the concurrency limit slot of the login and the
+/// remote host, so backends should keep it until the conversation is finished
+pub struct Request {
+    pub(crate) service: String,
+    pub(crate) login: String,
+    pub(crate) priority: Priority,
+    pub(crate) remote_host: Option<String>,
+    pub(crate) permit: Option<Permit>,
+    pub(crate) lockout: Option<Arc<Lockout>>,
+    pub(crate) fail_at: Option<Instant>,
+}
+
+impl Request {
+    pub fn new<S: Into<String>, L: Into<String>>(service: S, login: L) -> Self {
+        Self {
+            service: service.into(),
+            login: login.into(),
+            priority: Priority::default(),
+            remote_host: None,
+            permit: None,
+            lockout: None,
+            fail_at: None,
+        }
+    }
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    pub fn remote_host(&self) -> Option<&str> {
+        self.remote_host.as_deref()
+    }
+    /// Creates a conversation for the request, the outcomes must be sent with
+    /// [`ConversationPeer::send_outcome`]
+    pub fn conversation(&self) -> (Conversation, ConversationPeer) {
+        let (mut c, mut peer) = Conversation::pair();
+        c.outcomes_handled = true;
+        peer.outcome_policy = self.outcome_policy();
+        (c, peer)
+    }
+    pub(crate) fn outcome_policy(&self) -> Option<OutcomePolicy> {
+        if self.lockout.is_none() && self.fail_at.is_none() {
+            return None;
+        }
+        Some(OutcomePolicy {
+            login: self.login.clone(),
+            remote_host: self.remote_host.clone(),
+            lockout: self.lockout.clone(),
+            fail_at: self.fail_at,
+        })
+    }
+}
+
+// lockout counting and the minimum failure duration for custom backends (the libpam backend
+// applies them in the workers)
+pub(crate) struct OutcomePolicy {
+    login: String,
+    remote_host: Option<String>,
+    lockout: Option<Arc<Lockout>>,
+    fail_at: Option<Instant>,
+}
+
+impl OutcomePolicy {
+    fn apply(&self, outcome: &Message) {
+        if let Some(ref lockout) = self.lockout {
+            match outcome {
+                Message::AuthenticationFailed => {
+                    lockout.failure(&self.login, self.remote_host.as_deref());
+                }
+                Message::Authenticated => {
+                    lockout.success(&self.login, self.remote_host.as_deref());
+                }
+                _ => {}
+            }
+        }
+        if *outcome != Message::Authenticated
+            && let Some(fail_at) = self.fail_at
+        {
+            std::thread::sleep(fail_at.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+/// The backend side of a conversation, see [`Conversation::pair`]
+pub struct ConversationPeer {
+    pub(crate) msg_tx: Sender<Message>,
+    pub(crate) input_rx: Receiver<String>,
+    pub(crate) failure_code: Arc<AtomicI32>,
+    pub(crate) outcome_policy: Option<OutcomePolicy>,
+}
+
+impl ConversationPeer {
+    pub fn tx(&self) -> &Sender<Message> {
+        &self.msg_tx
+    }
+    pub fn rx(&self) -> &Receiver<String> {
+        &self.input_rx
+    }
+    /// Sets the code, reported by [`Conversation::failure_code`], must be set before a failure
+    /// outcome is sent
+    pub fn set_failure_code(&self, code: i32) {
+        self.failure_code.store(code, Ordering::Relaxed);
+    }
+    /// Sends the final outcome. For conversations created with [`Request::conversation`] the
+    /// lockout counters are updated and the call blocks until the minimum failure duration is
+    /// passed if the outcome is a failure
+    pub fn send_outcome(&self, outcome: Message) -> Result<()> {
+        if let Some(ref policy) = self.outcome_policy {
+            policy.apply(&outcome);
+        }
+        self.msg_tx.send_blocking(outcome)?;
+        Ok(())
+    }
+}
+
+// the default backend
+pub(crate) struct PamBackend {
+    pub(crate) default: PoolHandle,
+    pub(crate) services: BTreeMap<String, PoolHandle>,
+    pub(crate) capabilities: Capabilities,
+}
+
+impl PamBackend {
+    fn route(&self, service: &str) -> &PoolHandle {
+        self.services.get(service).unwrap_or(&self.default)
+    }
+    pub(crate) fn status(&self) -> PoolStatus {
+        self.default.status()
+    }
+    pub(crate) fn service_status(&self, service: &str) -> Option<PoolStatus> {
+        self.services.get(service).map(PoolHandle::status)
+    }
+}
+
+impl Backend for PamBackend {
+    fn chat_sync(&self, request: Request) -> Result<Conversation> {
+        self.route(&request.service).chat_sync(request)
+    }
+    #[cfg(feature = "async")]
+    fn chat(&self, request: Request) -> BackendFuture<'_> {
+        Box::pin(self.route(&request.service).chat(request))
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rtsc::locking::Mutex;
+use tracing::{error, trace};
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+// the cost is kept moderate, the hash is calculated by the caller on every cache lookup
+const HASH_MEMORY_KIB: u32 = 8192;
+const HASH_ITERATIONS: u32 = 1;
+
+struct Entry {
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+    expires: Instant,
+}
+
+// successful password verifications, keyed by service and login, passwords are kept as salted
+// argon2 hashes only
+pub(crate) struct CredentialCache {
+    ttl: Duration,
+    hasher: Argon2<'static>,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+impl CredentialCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        let params = Params::new(HASH_MEMORY_KIB, HASH_ITERATIONS, 1, Some(HASH_LEN))
+            .expect("invalid argon2 params");
+        Self {
+            ttl,
+            hasher: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+            entries: <_>::default(),
+        }
+    }
+    fn hash(&self, password: &str, salt: &[u8; SALT_LEN]) -> Option<[u8; HASH_LEN]> {
+        let mut hash = [0u8; HASH_LEN];
+        if let Err(e) = self
+            .hasher
+            .hash_password_into(password.as_bytes(), salt, &mut hash)
+        {
+            error!(error = %e, "Unable to hash the password");
+            return None;
+        }
+        Some(hash)
+    }
+    pub(crate) fn verify(&self, service: &str, login: &str, password: &str) -> bool {
+        let key = (service.to_owned(), login.to_owned());
+        let salt = {
+            let mut entries = self.entries.lock();
+            let Some(entry) = entries.get(&key) else {
+                return false;
+            };
+            if entry.expires <= Instant::now() {
+                entries.remove(&key);
+                return false;
+            }
+            entry.salt
+        };
+        // hashed without holding the lock
+        let Some(hash) = self.hash(password, &salt) else {
+            return false;
+        };
+        let entries = self.entries.lock();
+        let valid = entries
+            .get(&key)
+            .is_some_and(|entry| entry.salt == salt && crate::constant_time_eq(&entry.hash, &hash));
+        trace!(service, login, valid, "Credential cache lookup");
+        valid
+    }
+    pub(crate) fn insert(&self, service: &str, login: &str, password: &str) {
+        let mut salt = [0u8; SALT_LEN];
+        if !crate::fill_random(&mut salt) {
+            error!("Unable to generate a salt, the credentials are not cached");
+            return;
+        }
+        let Some(hash) = self.hash(password, &salt) else {
+            return;
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+        entries.retain(|_, entry| entry.expires > now);
+        entries.insert(
+            (service.to_owned(), login.to_owned()),
+            Entry {
+                salt,
+                hash,
+                expires: now + self.ttl,
+            },
+        );
+    }
+    pub(crate) fn invalidate(&self, service: &str, login: &str) {
+        self.entries
+            .lock()
+            .remove(&(service.to_owned(), login.to_owned()));
+    }
+    pub(crate) fn clear(&self) {
+        self.entries.lock().clear();
+    }
+}
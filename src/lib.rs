@@ -1,18 +1,79 @@
 use std::{
+    collections::BTreeMap,
     ffi::{CStr, CString, c_void},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
     ptr,
-    time::Duration,
+    sync::{
+        Arc,
+        atomic::{AtomicI32, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
-use libc::{c_char, c_int};
-use libloading::{Library, Symbol};
+use libc::{c_char, c_int, c_uint};
 use rtsc::channel_async::{Receiver, Sender};
-use tracing::{error, trace};
+use rtsc::data_policy::{DataDeliveryPolicy, DeliveryPolicy};
+use tracing::trace;
+
+#[cfg(feature = "async")]
+pub use backend::BackendFuture;
+pub use backend::{Backend, ConversationPeer, Request};
+use backend::{OutcomePolicy, PamBackend};
+#[cfg(feature = "cache")]
+use cache::CredentialCache;
+pub use library::Capabilities;
+use library::PamLibrary;
+use limits::{Limits, Permit};
+use lockout::Lockout;
+pub use lockout::LockoutConfig;
+pub use notice::Notice;
+#[cfg(feature = "offline")]
+pub use offline::OfflineConfig;
+#[cfg(feature = "offline")]
+use offline::OfflineStore;
+use pool::{PamCall, Pool, PoolHandle, Worker};
+pub use pool::{PoolConfig, PoolStatus};
+pub use prompt::PromptKind;
+use relay::relay;
+#[cfg(feature = "async")]
+pub use stream::{AnswerSink, MessageStream};
+#[cfg(feature = "transcript")]
+use transcript::{Recorder, Transcript};
+
+mod backend;
+#[cfg(feature = "cache")]
+mod cache;
+mod library;
+mod limits;
+mod lockout;
+mod notice;
+#[cfg(feature = "offline")]
+mod offline;
+mod pool;
+mod prompt;
+mod relay;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "terminal")]
+mod terminal;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "transcript")]
+pub mod transcript;
 
 const PAM_PROMPT_ECHO_OFF: c_int = 1;
-const PAM_PROMPT_ECHO_ON: c_int = 0;
-const PAM_ERROR_MSG: c_int = 2;
-const PAM_TEXT_INFO: c_int = 3;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+const PAM_RHOST: c_int = 4;
+const PAM_FAIL_DELAY: c_int = 10;
+
+const PAM_ABORT: c_int = 26;
+
+#[cfg(feature = "offline")]
+const PAM_AUTHINFO_UNAVAIL: c_int = 9;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -24,8 +85,21 @@ pub enum Error {
     Access(String),
     #[error("Function failed: {0}")]
     Failed(String),
+    #[cfg(feature = "dlopen")]
     #[error("Dynamic library error: {0}")]
     Library(#[from] libloading::Error),
+    #[error("No healthy PAM workers: {0}")]
+    NoHealthyWorkers(String),
+    #[error("Busy: the request queue is full")]
+    Busy,
+    #[error("Too many concurrent conversations for user {0}")]
+    TooManyForUser(String),
+    #[error("Too many concurrent conversations for host {0}")]
+    TooManyForHost(String),
+    #[error("Locked out after too many authentication failures, retry in {0:?}")]
+    LockedOut(Duration),
+    #[error("Cancelled by the user")]
+    Cancelled,
 }
 
 impl Error {
@@ -41,6 +115,13 @@ impl From<tokio::time::error::Elapsed> for Error {
     }
 }
 
+#[cfg(feature = "async")]
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Error::Failed(e.to_string())
+    }
+}
+
 impl From<oneshot::RecvTimeoutError> for Error {
     fn from(_: oneshot::RecvTimeoutError) -> Self {
         Error::Timeout
@@ -62,7 +143,9 @@ impl From<rtsc::Error> for Error {
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[repr(C)]
-struct PamHandleT;
+struct PamHandleT {
+    _private: [u8; 0],
+}
 
 #[repr(C)]
 struct PamMessage {
@@ -84,26 +167,99 @@ struct PamConv {
     appdata_ptr: *mut c_void,
 }
 
+/// What to do with a new request when the queue is full
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum AdmissionPolicy {
+    /// Reject the new request immediately with [`Error::Busy`]
+    Reject,
+    /// Wait for a free slot up to the timeout, then reject with [`Error::Busy`] (default)
+    #[default]
+    Wait,
+    /// Drop the oldest queued request (its caller gets [`Error::Busy`]) and queue the new one
+    ShedOldest,
+}
+
+/// Request priority, requests with higher priorities are taken from the queue first
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Priority {
+    /// Background requests, e.g. API clients
+    Low,
+    #[default]
+    Normal,
+    /// Interactive human logins, may use reserved workers
+    High,
+}
+
+impl Priority {
+    // rtsc queue priority, lower is better
+    fn queue_priority(self) -> usize {
+        match self {
+            Priority::Low => 200,
+            Priority::Normal => 100,
+            Priority::High => 10,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Authenticator {
-    tx: Sender<PamAuth>,
-    timeout: Duration,
+    inner: Arc<Inner>,
+    priority: Priority,
+    remote_host: Option<String>,
+}
+
+struct Inner {
+    backend: Arc<dyn Backend>,
+    // set if the default backend is used
+    pam: Option<Arc<PamBackend>>,
+    limits: Arc<Limits>,
+    lockout: Option<Arc<Lockout>>,
+    min_failure_duration: Option<Duration>,
+    failure_jitter: Duration,
+    #[cfg(feature = "cache")]
+    cache: Option<CredentialCache>,
+    #[cfg(feature = "offline")]
+    offline: Option<OfflineStore>,
+    #[cfg(feature = "transcript")]
+    recorder: Option<Recorder>,
 }
 
 pub struct AuthenticatorBuilder {
-    workers: u32,
-    queue_size: usize,
-    timeout: Duration,
-    chat_timeout: Duration,
+    pool: PoolConfig,
+    services: BTreeMap<String, PoolConfig>,
+    max_per_user: Option<usize>,
+    max_per_host: Option<usize>,
+    lockout: Option<LockoutConfig>,
+    min_failure_duration: Option<Duration>,
+    failure_jitter: Duration,
+    #[cfg(feature = "cache")]
+    cache_ttl: Option<Duration>,
+    #[cfg(feature = "offline")]
+    offline: Option<OfflineConfig>,
+    #[cfg(feature = "transcript")]
+    recorder: Option<Recorder>,
+    libraries: Vec<PathBuf>,
+    confdir: Option<PathBuf>,
 }
 
 impl Default for AuthenticatorBuilder {
     fn default() -> Self {
         AuthenticatorBuilder {
-            workers: 1,
-            queue_size: 10,
-            timeout: Duration::from_secs(5),
-            chat_timeout: Duration::from_secs(60),
+            pool: PoolConfig::default(),
+            services: BTreeMap::new(),
+            max_per_user: None,
+            max_per_host: None,
+            lockout: None,
+            min_failure_duration: None,
+            failure_jitter: Duration::ZERO,
+            #[cfg(feature = "cache")]
+            cache_ttl: None,
+            #[cfg(feature = "offline")]
+            offline: None,
+            #[cfg(feature = "transcript")]
+            recorder: None,
+            libraries: vec![library::DEFAULT_LIBRARY.into()],
+            confdir: None,
         }
     }
 }
@@ -112,52 +268,295 @@ impl AuthenticatorBuilder {
     pub fn new() -> Self {
         Self::default()
     }
+    /// Sets a fixed number of workers
     pub fn workers(mut self, workers: u32) -> Self {
-        self.workers = workers;
+        self.pool = self.pool.workers(workers);
+        self
+    }
+    /// Workers which are always kept running
+    pub fn min_workers(mut self, min_workers: u32) -> Self {
+        self.pool = self.pool.min_workers(min_workers);
+        self
+    }
+    /// The pool grows up to the specified number of workers when requests are queued
+    pub fn max_workers(mut self, max_workers: u32) -> Self {
+        self.pool = self.pool.max_workers(max_workers);
+        self
+    }
+    /// Workers above the minimum exit after being idle for the specified time (default: 60s)
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.pool = self.pool.idle_timeout(idle_timeout);
+        self
+    }
+    /// Additional workers, spawned above the maximum for [`Priority::High`] requests only, when
+    /// all the other workers are busy. A reserved worker processes a single request and exits
+    pub fn reserved_workers(mut self, reserved_workers: u32) -> Self {
+        self.pool = self.pool.reserved_workers(reserved_workers);
         self
     }
     pub fn queue_size(mut self, queue_size: usize) -> Self {
-        self.queue_size = queue_size;
+        self.pool = self.pool.queue_size(queue_size);
+        self
+    }
+    pub fn admission(mut self, admission: AdmissionPolicy) -> Self {
+        self.pool = self.pool.admission(admission);
         self
     }
     pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
+        self.pool = self.pool.timeout(timeout);
         self
     }
     pub fn chat_timeout(mut self, chat_timeout: Duration) -> Self {
-        self.chat_timeout = chat_timeout;
+        self.pool = self.pool.chat_timeout(chat_timeout);
+        self
+    }
+    /// Maximum time a worker may spend inside a single PAM call (not counting waiting for the
+    /// client answers). Workers stuck longer are abandoned and replaced with new ones, their
+    /// conversations are reported as failed. Disabled by default.
+    pub fn call_timeout(mut self, call_timeout: Duration) -> Self {
+        self.pool = self.pool.call_timeout(call_timeout);
+        self
+    }
+    /// Sets the default pool settings at once
+    pub fn pool(mut self, config: PoolConfig) -> Self {
+        self.pool = config;
+        self
+    }
+    /// Requests for the specified PAM service are processed by a dedicated worker pool
+    pub fn service<S: Into<String>>(mut self, service: S, config: PoolConfig) -> Self {
+        self.services.insert(service.into(), config);
+        self
+    }
+    /// Maximum number of concurrent conversations for the same login (across all services),
+    /// extra requests are rejected with [`Error::TooManyForUser`]
+    pub fn max_per_user(mut self, max: usize) -> Self {
+        self.max_per_user = Some(max);
+        self
+    }
+    /// Maximum number of concurrent conversations for the same remote host (see
+    /// [`Authenticator::with_remote_host`]), extra requests are rejected with
+    /// [`Error::TooManyForHost`]
+    pub fn max_per_host(mut self, max: usize) -> Self {
+        self.max_per_host = Some(max);
+        self
+    }
+    /// Enables brute-force protection: after too many failed authentications for the same login
+    /// or remote host, requests are rejected with [`Error::LockedOut`]
+    pub fn lockout(mut self, config: LockoutConfig) -> Self {
+        self.lockout = Some(config);
+        self
+    }
+    /// Failed outcomes are delivered not earlier than the specified time after the request start,
+    /// no matter which PAM stage has failed, so unknown users can not be told from wrong
+    /// passwords by the response time. Errors of the backend (e.g. a failed `pam_start`) are
+    /// returned not earlier as well. Should be set above the slowest failure of the stack
+    pub fn min_failure_duration(mut self, duration: Duration) -> Self {
+        self.min_failure_duration = Some(duration);
+        self
+    }
+    /// A random extra time (up to the specified value) added to the minimum failure duration
+    pub fn failure_jitter(mut self, jitter: Duration) -> Self {
+        self.failure_jitter = jitter;
         self
     }
+    /// Successful password verifications ([`Authenticator::authenticate`]) are cached for the
+    /// specified time, the repeated ones with the same password do not run the PAM stack. The
+    /// passwords are stored as salted argon2 hashes only
+    #[cfg(feature = "cache")]
+    pub fn credential_cache(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+    /// Enables offline authentication fallback for [`Authenticator::authenticate`]
+    #[cfg(feature = "offline")]
+    pub fn offline(mut self, config: OfflineConfig) -> Self {
+        self.offline = Some(config);
+        self
+    }
+    /// Records all conversations, the handler is called with a transcript when a conversation is
+    /// finished. The client answers are not recorded
+    #[cfg(feature = "transcript")]
+    pub fn record<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Transcript) + Send + Sync + 'static,
+    {
+        self.recorder = Some(Arc::new(handler));
+        self
+    }
+    /// libpam to load, either a file path or a library name, resolved by the dynamic linker
+    /// (default: `libpam.so.0`). If libpam is linked at build time (`link` feature), the
+    /// authenticator fails to build with a custom library
+    pub fn library<P: Into<PathBuf>>(mut self, library: P) -> Self {
+        self.libraries = vec![library.into()];
+        self
+    }
+    /// Libraries to try one by one, the first one which can be loaded and has got all the
+    /// required symbols is used. Fails with the `link` feature the same way as [`Self::library`]
+    pub fn library_search<I, P>(mut self, libraries: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.libraries = libraries.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Reads the service configurations from the specified directory instead of `/etc/pam.d`
+    /// (requires libpam 1.4+)
+    pub fn confdir<P: Into<PathBuf>>(mut self, confdir: P) -> Self {
+        self.confdir = Some(confdir.into());
+        self
+    }
+    /// Loads libpam and resolves the required symbols without starting workers
+    pub fn probe(&self) -> Result<Capabilities> {
+        Ok(PamLibrary::load(&self.libraries)?.capabilities())
+    }
     pub fn build(self) -> Result<Authenticator> {
-        Authenticator::new(
-            self.workers,
-            self.queue_size,
-            self.timeout,
-            self.chat_timeout,
-        )
+        let capabilities = self.probe()?;
+        trace!(?capabilities, "libpam probed");
+        // the workers load exactly the same library which has been probed
+        let libraries = capabilities
+            .path
+            .clone()
+            .map_or_else(|| self.libraries.clone(), |p| vec![p]);
+        let confdir = if let Some(ref confdir) = self.confdir {
+            if !capabilities.pam_start_confdir {
+                return Err(Error::Failed(
+                    "pam_start_confdir is not supported by libpam".to_owned(),
+                ));
+            }
+            Some(
+                CString::new(confdir.as_os_str().as_bytes())
+                    .map_err(|_| Error::access("invalid confdir"))?,
+            )
+        } else {
+            None
+        };
+        let default = PoolHandle::start(&self.pool, libraries.clone(), confdir.clone())?;
+        let mut services = BTreeMap::new();
+        for (service, config) in &self.services {
+            trace!("Starting PAM worker pool for service '{}'", service);
+            services.insert(
+                service.clone(),
+                PoolHandle::start(config, libraries.clone(), confdir.clone())?,
+            );
+        }
+        let pam = Arc::new(PamBackend {
+            default,
+            services,
+            capabilities,
+        });
+        self.build_inner(pam.clone(), Some(pam))
+    }
+    /// Builds an authenticator with a custom backend, the worker pool and the library settings are
+    /// ignored
+    pub fn build_with<B: Backend + 'static>(self, backend: B) -> Result<Authenticator> {
+        self.build_inner(Arc::new(backend), None)
+    }
+    fn build_inner(
+        self,
+        backend: Arc<dyn Backend>,
+        pam: Option<Arc<PamBackend>>,
+    ) -> Result<Authenticator> {
+        let lockout = self.lockout.map(Lockout::start).transpose()?;
+        #[cfg(feature = "offline")]
+        let offline = self.offline.map(OfflineStore::new).transpose()?;
+        Ok(Authenticator {
+            inner: Arc::new(Inner {
+                backend,
+                pam,
+                limits: Arc::new(Limits::new(self.max_per_user, self.max_per_host)),
+                lockout,
+                min_failure_duration: self.min_failure_duration,
+                failure_jitter: self.failure_jitter,
+                #[cfg(feature = "cache")]
+                cache: self.cache_ttl.map(CredentialCache::new),
+                #[cfg(feature = "offline")]
+                offline,
+                #[cfg(feature = "transcript")]
+                recorder: self.recorder,
+            }),
+            priority: Priority::default(),
+            remote_host: None,
+        })
     }
 }
 
 impl Authenticator {
-    fn new(
-        workers: u32,
-        queue_size: usize,
-        timeout: Duration,
-        chat_timeout: Duration,
-    ) -> Result<Self> {
-        let (tx, rx) = rtsc::channel_async::bounded(queue_size);
-        trace!("Starting {} PAM workers", workers);
-        for _ in 0..workers {
-            let rx = rx.clone();
-            std::thread::Builder::new()
-                .name("PAMworker".to_owned())
-                .spawn(move || {
-                    if let Err(e) = pam_worker(rx, timeout, chat_timeout) {
-                        error!(error = ?e, "PAM worker exited with error");
-                    }
-                })?;
+    /// Creates an authenticator with a custom backend and the default settings
+    ///
+    /// # Panics
+    ///
+    /// Should not panic, as the default settings require neither files nor threads. Use
+    /// [`AuthenticatorBuilder::build_with`] to get the errors
+    pub fn from_backend<B: Backend + 'static>(backend: B) -> Self {
+        AuthenticatorBuilder::new()
+            .build_with(backend)
+            .expect("the default settings are always valid")
+    }
+    /// Returns a handle which sends requests with the specified priority, the handle shares the
+    /// queues and the workers with the original authenticator
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
         }
-        Ok(Authenticator { tx, timeout })
+    }
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    /// Returns a handle which sends requests on behalf of the specified remote host. The host is
+    /// set as `PAM_RHOST` item for the modules and used for concurrency limits
+    pub fn with_remote_host<H: Into<String>>(&self, remote_host: H) -> Self {
+        Self {
+            remote_host: Some(remote_host.into()),
+            ..self.clone()
+        }
+    }
+    pub fn remote_host(&self) -> Option<&str> {
+        self.remote_host.as_deref()
+    }
+    /// Status of the default worker pool, `None` if a custom backend is used
+    pub fn status(&self) -> Option<PoolStatus> {
+        self.inner.pam.as_ref().map(|pam| pam.status())
+    }
+    /// Status of a dedicated service worker pool, `None` if the service has got no own pool or a
+    /// custom backend is used
+    pub fn service_status(&self, service: &str) -> Option<PoolStatus> {
+        self.inner
+            .pam
+            .as_ref()
+            .and_then(|pam| pam.service_status(service))
+    }
+    /// libpam capabilities, `None` if a custom backend is used
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.inner.pam.as_ref().map(|pam| &pam.capabilities)
+    }
+    fn request(&self, service: String, login: String) -> Result<Request> {
+        let started = Instant::now();
+        if let Some(ref lockout) = self.inner.lockout {
+            lockout.check(&login, self.remote_host.as_deref())?;
+        }
+        let permit = if self.inner.limits.is_enabled() {
+            Some(
+                self.inner
+                    .limits
+                    .acquire(&login, self.remote_host.as_deref())?,
+            )
+        } else {
+            None
+        };
+        Ok(Request {
+            service,
+            login,
+            priority: self.priority,
+            remote_host: self.remote_host.clone(),
+            permit,
+            lockout: self.inner.lockout.clone(),
+            fail_at: self
+                .inner
+                .min_failure_duration
+                .map(|d| started + d + jitter(self.inner.failure_jitter)),
+        })
     }
     #[cfg(feature = "async")]
     pub async fn chat<S, L>(&self, service: S, login: L) -> Result<Conversation>
@@ -165,42 +564,274 @@ impl Authenticator {
         S: Into<String>,
         L: Into<String>,
     {
-        let (res_tx, res_rx) = oneshot::channel();
-        let auth = PamAuth {
-            service: service.into(),
-            login: login.into(),
-            res_tx,
+        let (service, login) = (service.into(), login.into());
+        let req = self.request(service.clone(), login.clone())?;
+        let policy = req.outcome_policy();
+        let fail_at = req.fail_at;
+        let c = match self.inner.backend.chat(req).await {
+            Ok(c) => c,
+            Err(e) => {
+                if let Some(at) = fail_at {
+                    tokio::time::sleep_until(at.into()).await;
+                }
+                return Err(e);
+            }
         };
-        trace!(
-            "Sending PAM auth request for service '{}' and user '{}'",
-            auth.service, auth.login
-        );
-        tokio::time::timeout(self.timeout, self.tx.send(auth)).await??;
-        trace!("Waiting for PAM conversation");
-        tokio::time::timeout(self.timeout, res_rx).await??
+        let c = self.outcomes_handled(c, policy)?;
+        self.recorded(c, &service, &login)
     }
     pub fn chat_sync<S, L>(&self, service: S, login: L) -> Result<Conversation>
     where
         S: Into<String>,
         L: Into<String>,
     {
-        let (res_tx, res_rx) = oneshot::channel();
-        let auth = PamAuth {
-            service: service.into(),
-            login: login.into(),
-            res_tx,
+        let (service, login) = (service.into(), login.into());
+        let req = self.request(service.clone(), login.clone())?;
+        let policy = req.outcome_policy();
+        let fail_at = req.fail_at;
+        let c = self.inner.backend.chat_sync(req).inspect_err(|_| {
+            if let Some(at) = fail_at {
+                std::thread::sleep(at.saturating_duration_since(Instant::now()));
+            }
+        })?;
+        let c = self.outcomes_handled(c, policy)?;
+        self.recorded(c, &service, &login)
+    }
+    // conversations of custom backends, created without the request, are relayed to apply the
+    // outcome policy
+    fn outcomes_handled(
+        &self,
+        c: Conversation,
+        policy: Option<OutcomePolicy>,
+    ) -> Result<Conversation> {
+        if self.inner.pam.is_some() || c.outcomes_handled {
+            return Ok(c);
+        }
+        let Some(policy) = policy else {
+            return Ok(c);
         };
-        trace!(
-            "Sending PAM auth request for service '{}' and user '{}'",
-            auth.service, auth.login
-        );
-        self.tx.send_blocking_timeout(auth, self.timeout)?;
-        trace!("Waiting for PAM conversation");
-        res_rx.recv_timeout(self.timeout)?
+        let (outer, mut peer) = Conversation::pair();
+        peer.outcome_policy = Some(policy);
+        std::thread::Builder::new()
+            .name("PAMoutcome".to_owned())
+            .spawn(move || {
+                let Some(outcome) = relay(&c, &peer, |_| {}) else {
+                    return;
+                };
+                if let Some(code) = c.failure_code() {
+                    peer.set_failure_code(code);
+                }
+                peer.send_outcome(outcome).ok();
+            })?;
+        Ok(outer)
+    }
+    #[cfg(feature = "transcript")]
+    fn recorded(&self, c: Conversation, service: &str, login: &str) -> Result<Conversation> {
+        let Some(ref recorder) = self.inner.recorder else {
+            return Ok(c);
+        };
+        let recorder = recorder.clone();
+        c.record(service, login, move |transcript| recorder(transcript))
+    }
+    #[cfg(not(feature = "transcript"))]
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    fn recorded(&self, c: Conversation, _service: &str, _login: &str) -> Result<Conversation> {
+        Ok(c)
     }
+    #[cfg(feature = "cache")]
+    fn cached(&self, service: &str, login: &str, password: &str) -> Result<bool> {
+        let Some(ref cache) = self.inner.cache else {
+            return Ok(false);
+        };
+        if let Some(ref lockout) = self.inner.lockout {
+            lockout.check(login, self.remote_host.as_deref())?;
+        }
+        Ok(cache.verify(service, login, password))
+    }
+    #[cfg(not(feature = "cache"))]
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    fn cached(&self, _service: &str, _login: &str, _password: &str) -> Result<bool> {
+        Ok(false)
+    }
+    // the stores are feature-gated, without them the arguments are not used
+    #[allow(unused_variables, clippy::unused_self)]
+    fn finished(
+        &self,
+        failure_code: Option<i32>,
+        service: &str,
+        login: &str,
+        password: &str,
+        authenticated: bool,
+    ) -> bool {
+        if authenticated {
+            #[cfg(feature = "cache")]
+            if let Some(ref cache) = self.inner.cache {
+                cache.insert(service, login, password);
+            }
+            #[cfg(feature = "offline")]
+            if let Some(ref offline) = self.inner.offline {
+                offline.store(service, login, password);
+            }
+            return true;
+        }
+        #[cfg(feature = "offline")]
+        if let Some(ref offline) = self.inner.offline
+            && failure_code == Some(PAM_AUTHINFO_UNAVAIL)
+            && offline.is_enabled(service)
+        {
+            trace!("Authentication information is unavailable, trying offline verifier");
+            if offline.verify(service, login, password) {
+                if let Some(ref lockout) = self.inner.lockout {
+                    lockout.success(login, self.remote_host.as_deref());
+                }
+                return true;
+            }
+        }
+        false
+    }
+    /// Removes cached credentials of the login
+    #[cfg(feature = "cache")]
+    pub fn invalidate_credentials(&self, service: &str, login: &str) {
+        if let Some(ref cache) = self.inner.cache {
+            cache.invalidate(service, login);
+        }
+    }
+    /// Removes all cached credentials
+    #[cfg(feature = "cache")]
+    pub fn clear_credentials(&self) {
+        if let Some(ref cache) = self.inner.cache {
+            cache.clear();
+        }
+    }
+    /// A simple password authentication: password prompts are answered with the password, echo
+    /// prompts with the login. Returns `true` if the user has been authenticated and validated
+    #[cfg(feature = "async")]
+    pub async fn authenticate<S, L>(&self, service: S, login: L, password: &str) -> Result<bool>
+    where
+        S: Into<String>,
+        L: Into<String>,
+    {
+        let (service, login) = (service.into(), login.into());
+        let (s, l, p) = (service.clone(), login.clone(), password.to_owned());
+        if self
+            .unblocked(move |auth| auth.cached(&s, &l, &p))
+            .await??
+        {
+            return Ok(true);
+        }
+        let c = self.chat(service.as_str(), login.as_str()).await?;
+        loop {
+            match password_step(c.rx().recv().await?, &login, password) {
+                PasswordStep::Reply(reply) => c.tx().send(reply).await?,
+                PasswordStep::Skip => {}
+                PasswordStep::Done(authenticated) => {
+                    let failure_code = c.failure_code();
+                    let password = password.to_owned();
+                    return self
+                        .unblocked(move |auth| {
+                            auth.finished(failure_code, &service, &login, &password, authenticated)
+                        })
+                        .await;
+                }
+            }
+        }
+    }
+    // the credential cache and the offline store hash passwords and write files, which must not
+    // block the async runtime
+    #[cfg(feature = "async")]
+    async fn unblocked<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Authenticator) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        #[allow(unused_mut)]
+        let mut blocking = false;
+        #[cfg(feature = "cache")]
+        {
+            blocking |= self.inner.cache.is_some();
+        }
+        #[cfg(feature = "offline")]
+        {
+            blocking |= self.inner.offline.is_some();
+        }
+        if !blocking {
+            return Ok(f(self));
+        }
+        let auth = self.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&auth)).await?)
+    }
+    /// Sync version of [`Authenticator::authenticate`]. With the credential cache or the offline
+    /// verifier enabled, the call hashes the password and may write the offline store file, so it
+    /// must not be called from async tasks
+    pub fn authenticate_sync<S, L>(&self, service: S, login: L, password: &str) -> Result<bool>
+    where
+        S: Into<String>,
+        L: Into<String>,
+    {
+        let (service, login) = (service.into(), login.into());
+        if self.cached(&service, &login, password)? {
+            return Ok(true);
+        }
+        let c = self.chat_sync(service.as_str(), login.as_str())?;
+        loop {
+            match password_step(c.rx().recv_blocking()?, &login, password) {
+                PasswordStep::Reply(reply) => c.tx().send_blocking(reply)?,
+                PasswordStep::Skip => {}
+                PasswordStep::Done(authenticated) => {
+                    return Ok(self.finished(
+                        c.failure_code(),
+                        &service,
+                        &login,
+                        password,
+                        authenticated,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+enum PasswordStep {
+    Reply(String),
+    Skip,
+    Done(bool),
+}
+
+fn password_step(msg: Message, login: &str, password: &str) -> PasswordStep {
+    match msg {
+        Message::NoEcho(_) => PasswordStep::Reply(password.to_owned()),
+        Message::Echo(_) => PasswordStep::Reply(login.to_owned()),
+        Message::Info(_) | Message::Error(_) => PasswordStep::Skip,
+        Message::AuthenticationFailed | Message::ValidationFailed => PasswordStep::Done(false),
+        Message::Authenticated => PasswordStep::Done(true),
+    }
+}
+
+#[cfg(any(feature = "cache", feature = "offline"))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn fill_random(buf: &mut [u8]) -> bool {
+    let n = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+    usize::try_from(n).ok() == Some(buf.len())
+}
+
+// a random duration up to the maximum
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return max;
+    }
+    let mut buf = [0u8; 8];
+    if !fill_random(&mut buf) {
+        return max;
+    }
+    let nanos = u64::try_from(max.as_nanos()).unwrap_or(u64::MAX);
+    Duration::from_nanos(u64::from_ne_bytes(buf) % nanos)
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "transcript", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     Echo(String),
     NoEcho(String),
@@ -214,6 +845,9 @@ pub enum Message {
 pub struct Conversation {
     msg_rx: Receiver<Message>,
     input_tx: Sender<String>,
+    failure_code: Arc<AtomicI32>,
+    // created with Request::conversation, the backend applies the outcome policy
+    outcomes_handled: bool,
 }
 
 struct ConversationPam {
@@ -221,81 +855,204 @@ struct ConversationPam {
     input_rx: Receiver<String>,
     timeout: Duration,
     chat_timeout: Duration,
+    worker: Arc<Worker>,
+    // requested by the modules with pam_fail_delay, microseconds
+    fail_delay: AtomicU32,
+    failure_code: Arc<AtomicI32>,
+}
+
+impl ConversationPam {
+    // the failed outcome is delivered after the fail delay and not before the minimum failure
+    // duration, the worker is not blocked
+    fn send_failure(
+        &self,
+        pool: &Pool,
+        auth: &PamAuth,
+        permit: Option<Permit>,
+        msg: Message,
+        rc: c_int,
+    ) {
+        self.failure_code.store(rc, Ordering::Relaxed);
+        let now = Instant::now();
+        let delay = Duration::from_micros(self.fail_delay.load(Ordering::Relaxed).into());
+        let at = auth
+            .fail_at
+            .map_or(now + delay, |fail_at| fail_at.max(now + delay));
+        if at <= now {
+            // the slot is released before the outcome, so the caller may start a new conversation
+            drop(permit);
+            self.msg_tx.send_blocking_timeout(msg, self.timeout).ok();
+        } else {
+            pool.delay_outcome(self.msg_tx.clone(), msg, at, permit);
+        }
+    }
 }
 
 impl Conversation {
+    /// Creates a conversation and its backend side, used by custom backends
+    pub fn pair() -> (Conversation, ConversationPeer) {
+        let (msg_tx, msg_rx) = rtsc::channel_async::bounded(10);
+        let (input_tx, input_rx) = rtsc::channel_async::bounded(10);
+        let failure_code = Arc::new(AtomicI32::new(0));
+        (
+            Conversation {
+                msg_rx,
+                input_tx,
+                failure_code: failure_code.clone(),
+                outcomes_handled: false,
+            },
+            ConversationPeer {
+                msg_tx,
+                input_rx,
+                failure_code,
+                outcome_policy: None,
+            },
+        )
+    }
     pub fn tx(&self) -> &Sender<String> {
         &self.input_tx
     }
     pub fn rx(&self) -> &Receiver<Message> {
         &self.msg_rx
     }
+    /// PAM return code of the failed call, available after a failure outcome is received
+    pub fn failure_code(&self) -> Option<i32> {
+        let rc = self.failure_code.load(Ordering::Relaxed);
+        (rc != 0).then_some(rc)
+    }
 }
 
+type PamAuthResult = oneshot::Receiver<Result<Conversation>>;
+
 struct PamAuth {
     service: String,
     login: String,
-    res_tx: oneshot::Sender<Result<Conversation>>,
+    remote_host: Option<String>,
+    // moved to the worker state when the conversation is started
+    permit: Option<Permit>,
+    lockout: Option<Arc<Lockout>>,
+    // failed outcomes are not delivered earlier
+    fail_at: Option<Instant>,
+    delivery_policy: DeliveryPolicy,
+    priority: Priority,
+    // the caller stops waiting for the conversation after the deadline
+    deadline: Instant,
+    res_tx: Option<oneshot::Sender<Result<Conversation>>>,
+}
+
+impl PamAuth {
+    fn respond(&mut self, res: Result<Conversation>) {
+        if let Some(res_tx) = self.res_tx.take() {
+            res_tx.send(res).ok();
+        }
+    }
+    // the final conversation outcome
+    fn report(&self, outcome: &Message) {
+        let Some(ref lockout) = self.lockout else {
+            return;
+        };
+        match outcome {
+            Message::AuthenticationFailed => {
+                lockout.failure(&self.login, self.remote_host.as_deref());
+            }
+            Message::Authenticated => lockout.success(&self.login, self.remote_host.as_deref()),
+            _ => {}
+        }
+    }
+}
+
+impl DataDeliveryPolicy for PamAuth {
+    fn delivery_policy(&self) -> DeliveryPolicy {
+        self.delivery_policy
+    }
+    fn priority(&self) -> usize {
+        self.priority.queue_priority()
+    }
+    // a new request may shed queued ones with the same or lower priorities only
+    fn eq_kind(&self, other: &Self) -> bool {
+        self.priority <= other.priority
+    }
+    // nobody is going to receive the conversation, such requests are skipped by the workers
+    fn is_expired(&self) -> bool {
+        self.deadline <= Instant::now()
+            || self.res_tx.as_ref().is_none_or(oneshot::Sender::is_closed)
+    }
+}
+
+// a request dropped without a response has been shed from the queue
+impl Drop for PamAuth {
+    fn drop(&mut self) {
+        self.respond(Err(Error::Busy));
+    }
 }
 
 #[allow(clippy::too_many_lines)]
-fn pam_worker(rx: Receiver<PamAuth>, timeout: Duration, chat_timeout: Duration) -> Result<()> {
+fn pam_worker(pool: &Pool, worker: &Arc<Worker>) -> Result<()> {
     trace!("Starting PAM worker thread");
+    let timeout = pool.timeout;
+    let chat_timeout = pool.chat_timeout;
+    let lib = PamLibrary::load(&pool.libraries)?;
+    let pam_start = lib.pam_start;
+    let pam_start_confdir = match (&pool.confdir, lib.pam_start_confdir) {
+        (Some(confdir), Some(f)) => Some((confdir, f)),
+        (Some(_), None) => {
+            return Err(Error::Failed(
+                "pam_start_confdir is not supported by libpam".to_owned(),
+            ));
+        }
+        (None, _) => None,
+    };
+    let pam_authenticate = lib.pam_authenticate;
+    let pam_acct_mgmt = lib.pam_acct_mgmt;
+    let pam_end = lib.pam_end;
+    let pam_set_item = lib.pam_set_item;
     unsafe {
-        trace!("Loading libpam");
-        let lib = Library::new("libpam.so.0")?;
-        trace!("Resolving pam_start");
-        let pam_start: Symbol<
-            unsafe extern "C" fn(
-                *const c_char,
-                *const c_char,
-                *const PamConv,
-                *mut *mut PamHandleT,
-            ) -> c_int,
-        > = lib.get(b"pam_start\0")?;
-        trace!("Resolving pam_authenticate");
-        let pam_authenticate: Symbol<unsafe extern "C" fn(*mut PamHandleT, c_int) -> c_int> =
-            lib.get(b"pam_authenticate\0")?;
-        trace!("Resolving pam_acct_mgmt");
-        let pam_acct_mgmt: Symbol<unsafe extern "C" fn(*mut PamHandleT, c_int) -> c_int> =
-            lib.get(b"pam_acct_mgmt\0")?;
-        trace!("Resolving pam_end");
-        let pam_end: Symbol<unsafe extern "C" fn(*mut PamHandleT, c_int) -> c_int> =
-            lib.get(b"pam_end\0")?;
         trace!("Entering PAM worker loop");
-        while let Ok(auth) = rx.recv_blocking() {
+        worker.set_ready();
+        pool.worker_ready();
+        while let Some(mut auth) = pool.recv(worker) {
             trace!(
                 "Starting PAM conversation for user '{}', service '{}'",
                 auth.login, auth.service
             );
-            let c_service = match CString::new(auth.service) {
+            let c_service = match CString::new(std::mem::take(&mut auth.service)) {
                 Ok(s) => s,
                 Err(e) => {
                     trace!(error = ?e, "Failed to convert service name to CString");
-                    auth.res_tx
-                        .send(Err(Error::access("invalid service name")))
-                        .ok();
+                    auth.respond(Err(Error::access("invalid service name")));
                     continue;
                 }
             };
-            let c_user = match CString::new(auth.login) {
+            let c_user = match CString::new(auth.login.as_str()) {
                 Ok(s) => s,
                 Err(e) => {
                     trace!(error = ?e, "Failed to convert user name to CString");
-                    auth.res_tx
-                        .send(Err(Error::access("invalid user name")))
-                        .ok();
+                    auth.respond(Err(Error::access("invalid user name")));
+                    continue;
+                }
+            };
+            let c_rhost = match auth.remote_host.as_deref().map(CString::new).transpose() {
+                Ok(s) => s,
+                Err(e) => {
+                    trace!(error = ?e, "Failed to convert remote host to CString");
+                    auth.respond(Err(Error::access("invalid remote host")));
                     continue;
                 }
             };
-            let (msg_tx, msg_rx) = rtsc::channel_async::bounded(10);
-            let (input_tx, input_rx) = rtsc::channel_async::bounded(10);
-            let c = Conversation { msg_rx, input_tx };
+            if auth.is_expired() {
+                trace!("PAM auth request expired or abandoned by the caller, skipping");
+                continue;
+            }
+            let (c, peer) = Conversation::pair();
+            worker.begin(peer.msg_tx.clone(), &mut auth);
             let c_pam = ConversationPam {
-                msg_tx,
-                input_rx,
+                msg_tx: peer.msg_tx,
+                input_rx: peer.input_rx,
                 timeout,
                 chat_timeout,
+                worker: worker.clone(),
+                fail_delay: AtomicU32::new(0),
+                failure_code: peer.failure_code,
             };
             let mut pamh: *mut PamHandleT = ptr::null_mut();
             let c_raw = Box::into_raw(Box::new(c_pam)).cast::<c_void>();
@@ -303,47 +1060,110 @@ fn pam_worker(rx: Receiver<PamAuth>, timeout: Duration, chat_timeout: Duration)
                 conv: Some(conv),
                 appdata_ptr: c_raw,
             };
+            // the watchdog has already reported the conversation as failed, the worker is no
+            // longer a part of the pool
+            macro_rules! exit_abandoned {
+                () => {{
+                    trace!("PAM worker has been abandoned, exiting");
+                    pam_end(pamh, 1);
+                    let _ = Box::from_raw(c_raw.cast::<ConversationPam>());
+                    return Ok(());
+                }};
+            }
             trace!("Calling pam_start");
-            if pam_start(
-                c_service.as_ptr(),
-                c_user.as_ptr(),
-                &raw const conv,
-                &raw mut pamh,
-            ) != 0
-            {
+            worker.enter(PamCall::Start);
+            let rc = if let Some((confdir, pam_start_confdir)) = pam_start_confdir {
+                pam_start_confdir(
+                    c_service.as_ptr(),
+                    c_user.as_ptr(),
+                    &raw const conv,
+                    confdir.as_ptr(),
+                    &raw mut pamh,
+                )
+            } else {
+                pam_start(
+                    c_service.as_ptr(),
+                    c_user.as_ptr(),
+                    &raw const conv,
+                    &raw mut pamh,
+                )
+            };
+            if worker.leave() {
+                exit_abandoned!();
+            }
+            if rc != 0 {
                 pam_end(pamh, 1);
-                let _ = Box::from_raw(c_raw.cast::<Conversation>());
-                auth.res_tx
-                    .send(Err(Error::access("pam_start failed")))
-                    .ok();
+                let _ = Box::from_raw(c_raw.cast::<ConversationPam>());
+                worker.respond(Err(Error::access("pam_start failed")));
+                worker.finish();
                 continue;
             }
+            // libpam calls the function instead of sleeping in the worker after a failure
+            if let Some(pam_set_item) = pam_set_item {
+                let delay_fn: FailDelayFn = fail_delay;
+                if pam_set_item(pamh, PAM_FAIL_DELAY, delay_fn as *const c_void) != 0 {
+                    trace!("Unable to set PAM_FAIL_DELAY");
+                }
+            }
+            if let Some(ref rhost) = c_rhost {
+                if let Some(pam_set_item) = pam_set_item {
+                    trace!("Setting PAM_RHOST");
+                    if pam_set_item(pamh, PAM_RHOST, rhost.as_ptr().cast()) != 0 {
+                        pam_end(pamh, 1);
+                        let _ = Box::from_raw(c_raw.cast::<ConversationPam>());
+                        worker.respond(Err(Error::access("unable to set PAM_RHOST")));
+                        worker.finish();
+                        continue;
+                    }
+                } else {
+                    trace!("pam_set_item is not available, PAM_RHOST is not set");
+                }
+            }
             trace!("PAM conversation started, sending conversation to caller");
-            auth.res_tx.send(Ok(c)).ok();
+            if !worker.respond(Ok(c)) {
+                // the stack is not run, so the modules do not count a failed attempt
+                trace!("The caller has gone away, aborting PAM transaction");
+                pam_end(pamh, PAM_ABORT);
+                let _ = Box::from_raw(c_raw.cast::<ConversationPam>());
+                worker.finish();
+                continue;
+            }
             trace!("Calling pam_authenticate");
-            if pam_authenticate(pamh, 0) != 0 {
+            worker.enter(PamCall::Authenticate);
+            let rc = pam_authenticate(pamh, 0);
+            if worker.leave() {
+                exit_abandoned!();
+            }
+            if rc != 0 {
                 pam_end(pamh, 1);
                 let c = Box::from_raw(c_raw.cast::<ConversationPam>());
+                let permit = worker.finish();
                 trace!("Authentication failed");
-                c.msg_tx
-                    .send_blocking_timeout(Message::AuthenticationFailed, timeout)
-                    .ok();
+                auth.report(&Message::AuthenticationFailed);
+                c.send_failure(pool, &auth, permit, Message::AuthenticationFailed, rc);
                 continue;
             }
             trace!("Calling pam_acct_mgmt");
-            if pam_acct_mgmt(pamh, 0) != 0 {
+            worker.enter(PamCall::AcctMgmt);
+            let rc = pam_acct_mgmt(pamh, 0);
+            if worker.leave() {
+                exit_abandoned!();
+            }
+            if rc != 0 {
                 pam_end(pamh, 1);
                 let c = Box::from_raw(c_raw.cast::<ConversationPam>());
+                let permit = worker.finish();
                 trace!("Account management validation failed");
-                c.msg_tx
-                    .send_blocking_timeout(Message::ValidationFailed, timeout)
-                    .ok();
+                c.send_failure(pool, &auth, permit, Message::ValidationFailed, rc);
                 continue;
             }
             trace!("Calling pam_end");
             pam_end(pamh, 0);
             trace!("PAM authentication successful");
             let c = Box::from_raw(c_raw.cast::<ConversationPam>());
+            let permit = worker.finish();
+            auth.report(&Message::Authenticated);
+            drop(permit);
             c.msg_tx
                 .send_blocking_timeout(Message::Authenticated, timeout)
                 .ok();
@@ -353,6 +1173,18 @@ fn pam_worker(rx: Receiver<PamAuth>, timeout: Duration, chat_timeout: Duration)
     Ok(())
 }
 
+type FailDelayFn = extern "C" fn(c_int, c_uint, *mut c_void);
+
+// PAM_FAIL_DELAY callback, appdata_ptr is the one of the conversation
+extern "C" fn fail_delay(retval: c_int, usec_delay: c_uint, appdata_ptr: *mut c_void) {
+    if retval == 0 || appdata_ptr.is_null() {
+        return;
+    }
+    let c: &ConversationPam = unsafe { &*appdata_ptr.cast::<ConversationPam>() };
+    trace!(usec_delay, "PAM fail delay requested");
+    c.fail_delay.store(usec_delay, Ordering::Relaxed);
+}
+
 #[allow(clippy::too_many_lines)]
 extern "C" fn conv(
     num_msg: c_int,
@@ -374,6 +1206,11 @@ extern "C" fn conv(
             }
         };
         let c: &ConversationPam = &*appdata_ptr.cast::<ConversationPam>();
+        if c.worker.is_abandoned() {
+            trace!("PAM worker has been abandoned, aborting the conversation");
+            abort!();
+        }
+        let _pause = c.worker.pause();
         let mut reply_msgs = Vec::with_capacity(num_msg);
         for i in 0..num_msg {
             let m = *msg.add(i);
@@ -427,6 +1264,8 @@ extern "C" fn conv(
                         trace!(error = ?e, "Failed to send PAM Error message to client");
                         abort!();
                     }
+                    // the responses are matched with the messages by index
+                    reply_msgs.push(None);
                     continue;
                 }
                 PAM_TEXT_INFO => {
@@ -440,6 +1279,8 @@ extern "C" fn conv(
                         trace!(error = ?e, "Failed to send PAM Info message to client");
                         abort!();
                     }
+                    // the responses are matched with the messages by index
+                    reply_msgs.push(None);
                     continue;
                 }
                 style => {
@@ -454,7 +1295,7 @@ extern "C" fn conv(
                     abort!();
                 }
             };
-            reply_msgs.push(message);
+            reply_msgs.push(Some(message));
         }
         let replies =
             libc::calloc(num_msg, std::mem::size_of::<PamResponse>()).cast::<PamResponse>();
@@ -463,7 +1304,9 @@ extern "C" fn conv(
             abort!();
         }
         for (i, message) in reply_msgs.into_iter().enumerate() {
-            (*replies.add(i)).resp = libc::strdup(message.as_ptr());
+            if let Some(message) = message {
+                (*replies.add(i)).resp = libc::strdup(message.as_ptr());
+            }
             (*replies.add(i)).resp_retcode = 0;
         }
         *resp = replies;
@@ -0,0 +1,211 @@
+#[cfg(not(feature = "link"))]
+use std::ffi::CStr;
+use std::{
+    ffi::c_void,
+    path::{Path, PathBuf},
+};
+
+use libc::{c_char, c_int};
+#[cfg(not(feature = "link"))]
+use libloading::Library;
+use tracing::trace;
+
+use crate::{Error, PamConv, PamHandleT, Result};
+
+#[cfg(not(any(feature = "dlopen", feature = "link")))]
+compile_error!("either `dlopen` or `link` feature must be enabled");
+
+pub(crate) const DEFAULT_LIBRARY: &str = "libpam.so.0";
+
+pub(crate) type PamStartFn = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    *const PamConv,
+    *mut *mut PamHandleT,
+) -> c_int;
+pub(crate) type PamStartConfdirFn = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    *const PamConv,
+    *const c_char,
+    *mut *mut PamHandleT,
+) -> c_int;
+pub(crate) type PamHandleFn = unsafe extern "C" fn(*mut PamHandleT, c_int) -> c_int;
+pub(crate) type PamSetItemFn = unsafe extern "C" fn(*mut PamHandleT, c_int, *const c_void) -> c_int;
+
+#[cfg(feature = "link")]
+#[link(name = "pam")]
+unsafe extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut PamHandleT,
+    ) -> c_int;
+    fn pam_authenticate(pamh: *mut PamHandleT, flags: c_int) -> c_int;
+    fn pam_acct_mgmt(pamh: *mut PamHandleT, flags: c_int) -> c_int;
+    fn pam_end(pamh: *mut PamHandleT, pam_status: c_int) -> c_int;
+    fn pam_set_item(pamh: *mut PamHandleT, item_type: c_int, item: *const c_void) -> c_int;
+}
+
+// libpam 1.4+
+#[cfg(feature = "link-confdir")]
+#[link(name = "pam")]
+unsafe extern "C" {
+    fn pam_start_confdir(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        confdir: *const c_char,
+        pamh: *mut *mut PamHandleT,
+    ) -> c_int;
+}
+
+/// libpam features, available in the loaded library. If libpam is linked at build time (`link`
+/// feature), the path and the version are unknown and the features are reported for the bound
+/// symbols
+// the flags mirror the optional libpam symbols one by one
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Capabilities {
+    /// The library file the symbols are resolved from
+    pub path: Option<PathBuf>,
+    /// The shared library version (taken from the library file name, e.g. `0.85.1`)
+    pub version: Option<String>,
+    pub pam_start_confdir: bool,
+    pub pam_fail_delay: bool,
+    pub pam_set_item: bool,
+    pub pam_get_item: bool,
+    pub pam_getenvlist: bool,
+    pub pam_strerror: bool,
+}
+
+// the fields are named after the libpam symbols
+#[allow(clippy::struct_field_names)]
+pub(crate) struct PamLibrary {
+    #[cfg(not(feature = "link"))]
+    lib: Library,
+    pub(crate) pam_start: PamStartFn,
+    // libpam 1.4+
+    pub(crate) pam_start_confdir: Option<PamStartConfdirFn>,
+    pub(crate) pam_authenticate: PamHandleFn,
+    pub(crate) pam_acct_mgmt: PamHandleFn,
+    pub(crate) pam_end: PamHandleFn,
+    pub(crate) pam_set_item: Option<PamSetItemFn>,
+}
+
+impl PamLibrary {
+    // libpam is linked at build time, a custom search list is rejected. pam_start_confdir is
+    // bound with `link-confdir` feature only, to keep linking with older libpam versions
+    #[cfg(feature = "link")]
+    pub(crate) fn load<P: AsRef<Path>>(search: &[P]) -> Result<Self> {
+        if search.len() != 1 || search[0].as_ref() != Path::new(DEFAULT_LIBRARY) {
+            return Err(Error::Failed(
+                "libpam is linked at build time, the library can not be set".to_owned(),
+            ));
+        }
+        trace!("Using libpam linked at build time");
+        Ok(Self {
+            pam_start,
+            #[cfg(feature = "link-confdir")]
+            pam_start_confdir: Some(pam_start_confdir),
+            #[cfg(not(feature = "link-confdir"))]
+            pam_start_confdir: None,
+            pam_authenticate,
+            pam_acct_mgmt,
+            pam_end,
+            pam_set_item: Some(pam_set_item),
+        })
+    }
+    // tries the libraries one by one, returns the error of the last one if none can be loaded
+    #[cfg(not(feature = "link"))]
+    pub(crate) fn load<P: AsRef<Path>>(search: &[P]) -> Result<Self> {
+        let mut err = None;
+        for path in search {
+            match Self::load_from(path.as_ref()) {
+                Ok(lib) => return Ok(lib),
+                Err(e) => {
+                    trace!(path = %path.as_ref().display(), error = ?e, "Unable to load libpam");
+                    err = Some(e);
+                }
+            }
+        }
+        Err(err.unwrap_or_else(|| Error::Failed("libpam search list is empty".to_owned())))
+    }
+    #[cfg(not(feature = "link"))]
+    fn load_from(path: &Path) -> Result<Self> {
+        unsafe {
+            trace!(path = %path.display(), "Loading libpam");
+            let lib = Library::new(path)?;
+            trace!("Resolving pam_start");
+            let pam_start = *lib.get::<PamStartFn>(b"pam_start\0")?;
+            trace!("Resolving pam_start_confdir");
+            let pam_start_confdir = lib
+                .get::<PamStartConfdirFn>(b"pam_start_confdir\0")
+                .ok()
+                .map(|s| *s);
+            trace!("Resolving pam_authenticate");
+            let pam_authenticate = *lib.get::<PamHandleFn>(b"pam_authenticate\0")?;
+            trace!("Resolving pam_acct_mgmt");
+            let pam_acct_mgmt = *lib.get::<PamHandleFn>(b"pam_acct_mgmt\0")?;
+            trace!("Resolving pam_end");
+            let pam_end = *lib.get::<PamHandleFn>(b"pam_end\0")?;
+            trace!("Resolving pam_set_item");
+            let pam_set_item = lib.get::<PamSetItemFn>(b"pam_set_item\0").ok().map(|s| *s);
+            Ok(Self {
+                lib,
+                pam_start,
+                pam_start_confdir,
+                pam_authenticate,
+                pam_acct_mgmt,
+                pam_end,
+                pam_set_item,
+            })
+        }
+    }
+    // the symbols, which are not bound, are exported by all libpam versions
+    #[cfg(feature = "link")]
+    fn has_symbol(&self, name: &[u8]) -> bool {
+        name != b"pam_start_confdir\0" || self.pam_start_confdir.is_some()
+    }
+    #[cfg(not(feature = "link"))]
+    fn has_symbol(&self, name: &[u8]) -> bool {
+        unsafe { self.lib.get::<*const c_void>(name).is_ok() }
+    }
+    #[cfg(feature = "link")]
+    #[allow(clippy::unused_self)]
+    fn path(&self) -> Option<PathBuf> {
+        None
+    }
+    #[cfg(not(feature = "link"))]
+    fn path(&self) -> Option<PathBuf> {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(self.pam_start as *const c_void, &raw mut info) } == 0
+            || info.dli_fname.is_null()
+        {
+            return None;
+        }
+        let path = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+        let path = PathBuf::from(path.as_ref());
+        Some(std::fs::canonicalize(&path).unwrap_or(path))
+    }
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        let path = self.path();
+        let version = path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.split_once(".so."))
+            .map(|(_, v)| v.to_owned());
+        Capabilities {
+            path,
+            version,
+            pam_start_confdir: self.has_symbol(b"pam_start_confdir\0"),
+            pam_fail_delay: self.has_symbol(b"pam_fail_delay\0"),
+            pam_set_item: self.has_symbol(b"pam_set_item\0"),
+            pam_get_item: self.has_symbol(b"pam_get_item\0"),
+            pam_getenvlist: self.has_symbol(b"pam_getenvlist\0"),
+            pam_strerror: self.has_symbol(b"pam_strerror\0"),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::HashMap, sync::Arc};
+
+use rtsc::locking::Mutex;
+use tracing::trace;
+
+use crate::{Error, Result};
+
+// concurrent conversation limits per login and per remote host
+#[derive(Default)]
+pub(crate) struct Limits {
+    max_per_user: Option<usize>,
+    max_per_host: Option<usize>,
+    active: Mutex<Active>,
+}
+
+#[derive(Default)]
+struct Active {
+    users: HashMap<String, usize>,
+    hosts: HashMap<String, usize>,
+}
+
+impl Active {
+    fn release(&mut self, login: Option<&str>, host: Option<&str>) {
+        fn dec(map: &mut HashMap<String, usize>, key: &str) {
+            if let Some(n) = map.get_mut(key) {
+                *n -= 1;
+                if *n == 0 {
+                    map.remove(key);
+                }
+            }
+        }
+        if let Some(login) = login {
+            dec(&mut self.users, login);
+        }
+        if let Some(host) = host {
+            dec(&mut self.hosts, host);
+        }
+    }
+}
+
+impl Limits {
+    pub(crate) fn new(max_per_user: Option<usize>, max_per_host: Option<usize>) -> Self {
+        Self {
+            max_per_user,
+            max_per_host,
+            active: <_>::default(),
+        }
+    }
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_per_user.is_some() || self.max_per_host.is_some()
+    }
+    // the permit is carried with the request and released when the conversation is finished
+    pub(crate) fn acquire(self: &Arc<Self>, login: &str, host: Option<&str>) -> Result<Permit> {
+        let mut active = self.active.lock();
+        if let Some(max) = self.max_per_user
+            && active.users.get(login).copied().unwrap_or_default() >= max
+        {
+            trace!("Concurrent conversation limit reached for user '{}'", login);
+            return Err(Error::TooManyForUser(login.to_owned()));
+        }
+        if let Some(max) = self.max_per_host
+            && let Some(host) = host
+            && active.hosts.get(host).copied().unwrap_or_default() >= max
+        {
+            trace!("Concurrent conversation limit reached for host '{}'", host);
+            return Err(Error::TooManyForHost(host.to_owned()));
+        }
+        let login = self.max_per_user.map(|_| {
+            *active.users.entry(login.to_owned()).or_default() += 1;
+            login.to_owned()
+        });
+        let host = self.max_per_host.and(host).map(|host| {
+            *active.hosts.entry(host.to_owned()).or_default() += 1;
+            host.to_owned()
+        });
+        Ok(Permit {
+            limits: self.clone(),
+            login,
+            host,
+        })
+    }
+}
+
+pub(crate) struct Permit {
+    limits: Arc<Limits>,
+    login: Option<String>,
+    host: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limits
+            .active
+            .lock()
+            .release(self.login.as_deref(), self.host.as_deref());
+    }
+}
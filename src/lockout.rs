@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::Write as _,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rtsc::locking::Mutex;
+use tracing::{error, trace, warn};
+
+use crate::{Error, Result};
+
+// the state file is written by a background thread, not more often than this
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Brute-force protection settings. Failed authentications are counted per login and per remote
+/// host, after the threshold is reached, further requests are rejected with
+/// [`Error::LockedOut`] for an exponentially growing period of time.
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    threshold: u32,
+    lockout: Duration,
+    max_lockout: Duration,
+    reset_after: Duration,
+    per_user: bool,
+    per_host: bool,
+    max_entries: usize,
+    state_file: Option<PathBuf>,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(3600),
+            reset_after: Duration::from_secs(900),
+            per_user: true,
+            per_host: true,
+            max_entries: 100_000,
+            state_file: None,
+        }
+    }
+}
+
+impl LockoutConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Failures allowed before the first lockout (default: 5)
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold.max(1);
+        self
+    }
+    /// The first lockout duration, doubled for every next failure (default: 30s)
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.lockout = lockout;
+        self
+    }
+    /// Maximum lockout duration (default: 1h)
+    pub fn max_lockout(mut self, max_lockout: Duration) -> Self {
+        self.max_lockout = max_lockout;
+        self
+    }
+    /// Failure counters are reset if there were no failures for the specified time (default:
+    /// 15m)
+    pub fn reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+    /// Count failures per login (default: true)
+    pub fn per_user(mut self, per_user: bool) -> Self {
+        self.per_user = per_user;
+        self
+    }
+    /// Count failures per remote host (default: true)
+    pub fn per_host(mut self, per_host: bool) -> Self {
+        self.per_host = per_host;
+        self
+    }
+    /// Maximum number of tracked logins and hosts (default: 100000). If exceeded, the expired
+    /// entries are removed, then the oldest ones
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+    /// The state is saved to the file in background (at most once a second) and loaded on start
+    pub fn state_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+enum Kind {
+    User,
+    Host,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::User => "u",
+            Kind::Host => "h",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    failures: u32,
+    // unix time, ms
+    last_failure: u64,
+    locked_until: u64,
+}
+
+impl Entry {
+    fn is_expired(&self, now: u64, reset_after: u64) -> bool {
+        now.saturating_sub(self.last_failure) > reset_after && self.locked_until <= now
+    }
+}
+
+pub(crate) struct Lockout {
+    config: LockoutConfig,
+    entries: Mutex<HashMap<(Kind, String), Entry>>,
+    // changed since the last save
+    dirty: AtomicBool,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+fn duration_ms(d: Duration) -> u64 {
+    u64::try_from(d.as_millis()).unwrap_or(u64::MAX)
+}
+
+impl Lockout {
+    pub(crate) fn start(config: LockoutConfig) -> Result<Arc<Self>> {
+        let entries = if let Some(ref path) = config.state_file {
+            load(path)?
+        } else {
+            HashMap::new()
+        };
+        let persistent = config.state_file.is_some();
+        let lockout = Arc::new(Self {
+            config,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        });
+        if persistent {
+            let lockout_weak = Arc::downgrade(&lockout);
+            std::thread::Builder::new()
+                .name("PAMlockout".to_owned())
+                .spawn(move || saver(&lockout_weak))?;
+        }
+        Ok(lockout)
+    }
+    fn keys(&self, login: &str, host: Option<&str>) -> Vec<(Kind, String)> {
+        let mut keys = Vec::with_capacity(2);
+        if self.config.per_user {
+            keys.push((Kind::User, login.to_owned()));
+        }
+        if self.config.per_host
+            && let Some(host) = host
+        {
+            keys.push((Kind::Host, host.to_owned()));
+        }
+        keys
+    }
+    pub(crate) fn check(&self, login: &str, host: Option<&str>) -> Result<()> {
+        let now = now_ms();
+        let entries = self.entries.lock();
+        for key in self.keys(login, host) {
+            if let Some(entry) = entries.get(&key)
+                && entry.locked_until > now
+            {
+                trace!(kind = ?key.0, key = %key.1, "Locked out");
+                return Err(Error::LockedOut(Duration::from_millis(
+                    entry.locked_until - now,
+                )));
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn failure(&self, login: &str, host: Option<&str>) {
+        let now = now_ms();
+        let reset_after = duration_ms(self.config.reset_after);
+        let mut entries = self.entries.lock();
+        for key in self.keys(login, host) {
+            let entry = entries.entry(key.clone()).or_default();
+            if now.saturating_sub(entry.last_failure) > reset_after {
+                entry.failures = 0;
+            }
+            entry.failures = entry.failures.saturating_add(1);
+            entry.last_failure = now;
+            if entry.failures >= self.config.threshold {
+                let exp = (entry.failures - self.config.threshold).min(31);
+                let lockout = self
+                    .config
+                    .lockout
+                    .saturating_mul(1 << exp)
+                    .min(self.config.max_lockout);
+                entry.locked_until = now.saturating_add(duration_ms(lockout));
+                warn!(kind = ?key.0, key = %key.1, failures = entry.failures, ?lockout,
+                    "Too many authentication failures, locking out");
+            }
+        }
+        if entries.len() > self.config.max_entries {
+            self.shrink(&mut entries, now);
+        }
+        self.dirty.store(true, Ordering::Release);
+    }
+    // the host counters are not reset, otherwise a login to any valid account from the host
+    // would reset them
+    pub(crate) fn success(&self, login: &str, _host: Option<&str>) {
+        if !self.config.per_user {
+            return;
+        }
+        if self
+            .entries
+            .lock()
+            .remove(&(Kind::User, login.to_owned()))
+            .is_some()
+        {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+    // removes the expired entries, then the oldest ones (the ones which are not locked or unlocked
+    // earlier first) down to 90% of the maximum, so the map is not shrunk on every failure
+    fn shrink(&self, entries: &mut HashMap<(Kind, String), Entry>, now: u64) {
+        let reset_after = duration_ms(self.config.reset_after);
+        entries.retain(|_, entry| !entry.is_expired(now, reset_after));
+        let keep = self.config.max_entries - self.config.max_entries / 10;
+        if entries.len() > keep {
+            let mut by_age: Vec<_> = entries
+                .iter()
+                .map(|(key, entry)| (entry.locked_until, entry.last_failure, key.clone()))
+                .collect();
+            by_age.sort_unstable_by_key(|(locked_until, last_failure, _)| {
+                (*locked_until, *last_failure)
+            });
+            let evicted = entries.len() - keep;
+            for (_, _, key) in by_age.into_iter().take(evicted) {
+                entries.remove(&key);
+            }
+            warn!(
+                evicted,
+                "Too many lockout entries, the oldest ones are removed"
+            );
+        }
+    }
+    fn save(&self) {
+        let Some(ref path) = self.config.state_file else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let now = now_ms();
+        let reset_after = duration_ms(self.config.reset_after);
+        // the entries are copied, the file is written without holding the lock
+        let entries: Vec<((Kind, String), Entry)> = self
+            .entries
+            .lock()
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now, reset_after))
+            .map(|(key, entry)| (key.clone(), *entry))
+            .collect();
+        let mut data = String::new();
+        for ((kind, key), entry) in entries {
+            // keys which can not be stored in a single line are skipped
+            if key.contains('\n') {
+                continue;
+            }
+            let _ = writeln!(
+                data,
+                "{} {} {} {} {}",
+                kind.as_str(),
+                entry.failures,
+                entry.last_failure,
+                entry.locked_until,
+                key
+            );
+        }
+        if let Err(e) = save(path, &data) {
+            error!(error = ?e, path = %path.display(), "Unable to save lockout state");
+        }
+    }
+}
+
+// the last changes are saved when the lockout is dropped
+impl Drop for Lockout {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+fn saver(lockout: &Weak<Lockout>) {
+    loop {
+        std::thread::sleep(SAVE_INTERVAL);
+        let Some(lockout) = lockout.upgrade() else {
+            break;
+        };
+        lockout.save();
+    }
+}
+
+// the state (logins and remote hosts) is readable by the owner only
+fn save(path: &Path, data: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    f.write_all(data.as_bytes())?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+fn load(path: &Path) -> Result<HashMap<(Kind, String), Entry>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = HashMap::new();
+    for line in data.lines() {
+        let mut sp = line.splitn(5, ' ');
+        let (Some(kind), Some(failures), Some(last_failure), Some(locked_until), Some(key)) =
+            (sp.next(), sp.next(), sp.next(), sp.next(), sp.next())
+        else {
+            continue;
+        };
+        let kind = match kind {
+            "u" => Kind::User,
+            "h" => Kind::Host,
+            _ => continue,
+        };
+        let (Ok(failures), Ok(last_failure), Ok(locked_until)) =
+            (failures.parse(), last_failure.parse(), locked_until.parse())
+        else {
+            continue;
+        };
+        entries.insert(
+            (kind, key.to_owned()),
+            Entry {
+                failures,
+                last_failure,
+                locked_until,
+            },
+        );
+    }
+    trace!(path = %path.display(), entries = entries.len(), "Lockout state loaded");
+    Ok(entries)
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use crate::Message;
+
+// pam_unix, pam_pwquality/cracklib and pam_sss messages, which reject a new password
+const PASSWORD_REJECTIONS: &[&str] = &[
+    "you must choose a longer password",
+    "password has been already used",
+    "password unchanged",
+    "password is too short",
+    "password is too simple",
+    "password is a palindrome",
+    "password too short",
+];
+
+/// A well-known notice of a PAM module, parsed from an info or an error message, see
+/// [`Notice::parse`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Notice {
+    /// The password expires soon (pam_unix, pam_sss)
+    PasswordExpiresIn(Duration),
+    /// The password has expired and must be changed
+    PasswordExpired,
+    AccountExpired,
+    /// The account is locked after failed logins (pam_faillock, pam_tally2). The number of the
+    /// failed logins and the time left to unlock are reported in separate messages by some modules
+    AccountLocked {
+        failed_logins: Option<u32>,
+        unlock_in: Option<Duration>,
+    },
+    /// Failed login attempts since the last successful login (pam_faillock, pam_lastlog)
+    FailedAttempts(u32),
+    /// The last login record (pam_lastlog), the time is kept as formatted by the module
+    LastLogin {
+        time: String,
+        from: Option<String>,
+        tty: Option<String>,
+    },
+    /// The last failed login record (pam_lastlog)
+    LastFailedLogin {
+        time: String,
+        from: Option<String>,
+        tty: Option<String>,
+    },
+    /// A new password has been rejected by a quality check (pam_pwquality, pam_unix), the reason
+    /// is kept as-is
+    PasswordRejected(String),
+    /// The retyped new password does not match
+    PasswordMismatch,
+}
+
+impl Notice {
+    /// Parses a module message. The texts of common Linux-PAM modules in the C locale are
+    /// recognized, `None` is returned for the others
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let contains = |pattern: &str| find(text, pattern).is_some();
+        if let Some(rest) = after(text, "password will expire in ") {
+            return parse_duration(rest).map(Notice::PasswordExpiresIn);
+        }
+        if contains("account has expired") {
+            return Some(Notice::AccountExpired);
+        }
+        if contains("required to change your password immediately")
+            || contains("password has expired")
+            || contains("password expired")
+        {
+            return Some(Notice::PasswordExpired);
+        }
+        if let Some(rest) = after(text, "locked due to ") {
+            return Some(Notice::AccountLocked {
+                failed_logins: leading_number(rest),
+                unlock_in: None,
+            });
+        }
+        // pam_faillock: "(N minutes left to unlock)", pam_tally2: "Account temporarily locked
+        // (N seconds left)"
+        if contains("left to unlock") || contains("temporarily locked") {
+            let unlock_in = text
+                .rsplit_once('(')
+                .and_then(|(_, rest)| parse_duration(rest));
+            return Some(Notice::AccountLocked {
+                failed_logins: None,
+                unlock_in,
+            });
+        }
+        if contains("since the last successful login") {
+            let count = after(text, "there were ")
+                .or_else(|| after(text, "there was "))
+                .and_then(leading_number)?;
+            return Some(Notice::FailedAttempts(count));
+        }
+        if let Some(rest) = after(text, "last failed login:") {
+            let (time, from, tty) = login_record(rest);
+            return Some(Notice::LastFailedLogin { time, from, tty });
+        }
+        if let Some(rest) = after(text, "last login:") {
+            let (time, from, tty) = login_record(rest);
+            return Some(Notice::LastLogin { time, from, tty });
+        }
+        if let Some(rest) = after(text, "bad password:") {
+            return Some(Notice::PasswordRejected(rest.trim().to_owned()));
+        }
+        if PASSWORD_REJECTIONS.iter().any(|r| contains(r)) {
+            return Some(Notice::PasswordRejected(text.to_owned()));
+        }
+        if contains("passwords do not match") || contains("passwords don't match") {
+            return Some(Notice::PasswordMismatch);
+        }
+        None
+    }
+}
+
+// case-insensitive, the patterns are ASCII so the position is always a char boundary
+fn find(text: &str, pattern: &str) -> Option<usize> {
+    text.as_bytes()
+        .windows(pattern.len())
+        .position(|w| w.eq_ignore_ascii_case(pattern.as_bytes()))
+}
+
+fn after<'a>(text: &'a str, pattern: &str) -> Option<&'a str> {
+    find(text, pattern).map(|pos| &text[pos + pattern.len()..])
+}
+
+fn leading_number(text: &str) -> Option<u32> {
+    let digits = text
+        .trim_start()
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?;
+    digits.parse().ok()
+}
+
+// "3 days", "1 hour" etc.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let value = u64::from(leading_number(text)?);
+    let unit = text
+        .trim_start()
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start()
+        .to_ascii_lowercase();
+    let secs = if unit.starts_with("second") {
+        1
+    } else if unit.starts_with("minute") {
+        60
+    } else if unit.starts_with("hour") {
+        3600
+    } else if unit.starts_with("day") {
+        86400
+    } else if unit.starts_with("week") {
+        604_800
+    } else {
+        return None;
+    };
+    Some(Duration::from_secs(value * secs))
+}
+
+// "Mon Jan  1 12:00:00 2024 from 10.0.0.1 on pts/0"
+fn login_record(text: &str) -> (String, Option<String>, Option<String>) {
+    let (rest, tty) = match text.rsplit_once(" on ") {
+        Some((rest, tty)) => (rest, Some(tty.trim().to_owned())),
+        None => (text, None),
+    };
+    let (time, from) = match rest.rsplit_once(" from ") {
+        Some((time, from)) => (time, Some(from.trim().to_owned())),
+        None => (rest, None),
+    };
+    (time.trim().to_owned(), from, tty)
+}
+
+impl Message {
+    /// A well-known module notice, `None` if the message is not an info or an error one or the
+    /// text is not recognized
+    pub fn notice(&self) -> Option<Notice> {
+        match self {
+            Message::Info(text) | Message::Error(text) => Notice::parse(text),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    io::Write as _,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use argon2::Argon2;
+use rtsc::locking::Mutex;
+use tracing::{error, trace, warn};
+
+use crate::Result;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Offline authentication settings. Verifiers (salted argon2 hashes) of the passwords of the last
+/// successful logins are kept in a local file. If the PAM stack reports that the authentication
+/// information is unavailable (`PAM_AUTHINFO_UNAVAIL`, e.g. a directory server is unreachable),
+/// [`crate::Authenticator::authenticate`] checks the password against the stored verifier.
+#[derive(Debug, Clone)]
+pub struct OfflineConfig {
+    path: PathBuf,
+    services: BTreeMap<String, Duration>,
+}
+
+impl OfflineConfig {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            services: BTreeMap::new(),
+        }
+    }
+    /// Enables the fallback for the service, verifiers older than the max age are not accepted
+    pub fn service<S: Into<String>>(mut self, service: S, max_age: Duration) -> Self {
+        self.services.insert(service.into(), max_age);
+        self
+    }
+}
+
+#[derive(Clone)]
+struct Verifier {
+    // unix time, ms
+    created: u64,
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+pub(crate) struct OfflineStore {
+    config: OfflineConfig,
+    hasher: Argon2<'static>,
+    verifiers: Mutex<HashMap<(String, String), Verifier>>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+impl OfflineStore {
+    pub(crate) fn new(config: OfflineConfig) -> Result<Self> {
+        let verifiers = load(&config.path)?;
+        Ok(Self {
+            config,
+            hasher: Argon2::default(),
+            verifiers: Mutex::new(verifiers),
+        })
+    }
+    pub(crate) fn is_enabled(&self, service: &str) -> bool {
+        self.config.services.contains_key(service)
+    }
+    fn hash(&self, password: &str, salt: &[u8; SALT_LEN]) -> Option<[u8; HASH_LEN]> {
+        let mut hash = [0u8; HASH_LEN];
+        if let Err(e) = self
+            .hasher
+            .hash_password_into(password.as_bytes(), salt, &mut hash)
+        {
+            error!(error = %e, "Unable to hash the password");
+            return None;
+        }
+        Some(hash)
+    }
+    // called after a successful online login
+    pub(crate) fn store(&self, service: &str, login: &str, password: &str) {
+        if !self.is_enabled(service) {
+            return;
+        }
+        let mut salt = [0u8; SALT_LEN];
+        if !crate::fill_random(&mut salt) {
+            error!("Unable to generate a salt, the offline verifier is not stored");
+            return;
+        }
+        let Some(hash) = self.hash(password, &salt) else {
+            return;
+        };
+        let mut verifiers = self.verifiers.lock();
+        verifiers.insert(
+            (service.to_owned(), login.to_owned()),
+            Verifier {
+                created: now_ms(),
+                salt,
+                hash,
+            },
+        );
+        self.save(&verifiers);
+    }
+    pub(crate) fn verify(&self, service: &str, login: &str, password: &str) -> bool {
+        let Some(max_age) = self.config.services.get(service) else {
+            return false;
+        };
+        let Some(verifier) = self
+            .verifiers
+            .lock()
+            .get(&(service.to_owned(), login.to_owned()))
+            .cloned()
+        else {
+            trace!(service, login, "No offline verifier");
+            return false;
+        };
+        let age = Duration::from_millis(now_ms().saturating_sub(verifier.created));
+        if age > *max_age {
+            trace!(service, login, ?age, "Offline verifier expired");
+            return false;
+        }
+        let valid = self
+            .hash(password, &verifier.salt)
+            .is_some_and(|hash| crate::constant_time_eq(&hash, &verifier.hash));
+        if valid {
+            warn!(service, login, ?age, "Authenticated offline");
+        }
+        valid
+    }
+    fn save(&self, verifiers: &HashMap<(String, String), Verifier>) {
+        let mut data = String::new();
+        for ((service, login), v) in verifiers {
+            if service.contains([' ', '\n']) || login.contains('\n') {
+                continue;
+            }
+            let _ = writeln!(
+                data,
+                "{} {} {} {} {}",
+                v.created,
+                hex(&v.salt),
+                hex(&v.hash),
+                service,
+                login
+            );
+        }
+        if let Err(e) = save(&self.config.path, &data) {
+            error!(error = ?e, path = %self.config.path.display(), "Unable to save offline verifiers");
+        }
+    }
+}
+
+// the verifiers are readable by the owner only
+fn save(path: &Path, data: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    f.write_all(data.as_bytes())?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+fn load(path: &Path) -> Result<HashMap<(String, String), Verifier>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut verifiers = HashMap::new();
+    for line in data.lines() {
+        let mut sp = line.splitn(5, ' ');
+        let (Some(created), Some(salt), Some(hash), Some(service), Some(login)) =
+            (sp.next(), sp.next(), sp.next(), sp.next(), sp.next())
+        else {
+            continue;
+        };
+        let (Ok(created), Some(salt), Some(hash)) = (created.parse(), unhex(salt), unhex(hash))
+        else {
+            continue;
+        };
+        verifiers.insert(
+            (service.to_owned(), login.to_owned()),
+            Verifier {
+                created,
+                salt,
+                hash,
+            },
+        );
+    }
+    trace!(path = %path.display(), verifiers = verifiers.len(), "Offline verifiers loaded");
+    Ok(verifiers)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 {
+        return None;
+    }
+    let mut buf = [0u8; N];
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(buf)
+}
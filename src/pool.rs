@@ -0,0 +1,771 @@
+use std::{
+    ffi::CString,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use rtsc::channel_async::{Receiver, Sender};
+use rtsc::data_policy::DeliveryPolicy;
+use rtsc::locking::Mutex;
+use rtsc::policy_channel_async as request_channel;
+use tracing::{error, trace, warn};
+
+use crate::limits::Permit;
+use crate::{
+    AdmissionPolicy, Conversation, Error, Message, PamAuth, PamAuthResult, Priority, Request,
+    Result, pam_worker,
+};
+
+const SUPERVISOR_INTERVAL: Duration = Duration::from_millis(100);
+const RESTART_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Worker pool settings
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    min_workers: u32,
+    max_workers: u32,
+    idle_timeout: Duration,
+    reserved_workers: u32,
+    queue_size: usize,
+    admission: AdmissionPolicy,
+    timeout: Duration,
+    chat_timeout: Duration,
+    call_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_workers: 1,
+            max_workers: 1,
+            idle_timeout: Duration::from_secs(60),
+            reserved_workers: 0,
+            queue_size: 10,
+            admission: AdmissionPolicy::default(),
+            timeout: Duration::from_secs(5),
+            chat_timeout: Duration::from_secs(60),
+            call_timeout: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets a fixed number of workers
+    pub fn workers(mut self, workers: u32) -> Self {
+        self.min_workers = workers;
+        self.max_workers = workers;
+        self
+    }
+    /// Workers which are always kept running
+    pub fn min_workers(mut self, min_workers: u32) -> Self {
+        self.min_workers = min_workers;
+        self.max_workers = self.max_workers.max(min_workers);
+        self
+    }
+    /// The pool grows up to the specified number of workers when requests are queued
+    pub fn max_workers(mut self, max_workers: u32) -> Self {
+        self.max_workers = max_workers;
+        self.min_workers = self.min_workers.min(max_workers);
+        self
+    }
+    /// Workers above the minimum exit after being idle for the specified time (default: 60s)
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+    /// Additional workers, spawned above the maximum for [`Priority::High`] requests only, when
+    /// all the other workers are busy. A reserved worker processes a single request and exits
+    pub fn reserved_workers(mut self, reserved_workers: u32) -> Self {
+        self.reserved_workers = reserved_workers;
+        self
+    }
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+    pub fn admission(mut self, admission: AdmissionPolicy) -> Self {
+        self.admission = admission;
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    pub fn chat_timeout(mut self, chat_timeout: Duration) -> Self {
+        self.chat_timeout = chat_timeout;
+        self
+    }
+    /// Maximum time a worker may spend inside a single PAM call (not counting waiting for the
+    /// client answers). Workers stuck longer are abandoned and replaced with new ones, their
+    /// conversations are reported as failed. Disabled by default.
+    pub fn call_timeout(mut self, call_timeout: Duration) -> Self {
+        self.call_timeout = Some(call_timeout);
+        self
+    }
+}
+
+/// Worker pool status
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PoolStatus {
+    /// Workers started (including ones still loading libpam)
+    pub workers: usize,
+    /// Workers which have loaded libpam and are able to process requests
+    pub healthy: usize,
+    /// Workers processing conversations
+    pub busy: usize,
+    /// Requests waiting in the queue
+    pub queued: usize,
+    /// Workers which have failed and are waiting for a restart
+    pub restarts_pending: usize,
+    /// The most recent worker failure
+    pub last_error: Option<String>,
+}
+
+// the sending side of a pool, the workers exit when all handles are dropped
+pub(crate) struct PoolHandle {
+    tx: request_channel::Sender<PamAuth>,
+    admission: AdmissionPolicy,
+    timeout: Duration,
+    pool: Arc<Pool>,
+}
+
+impl PoolHandle {
+    pub(crate) fn start(
+        config: &PoolConfig,
+        libraries: Vec<PathBuf>,
+        confdir: Option<CString>,
+    ) -> Result<Self> {
+        let (tx, rx) = request_channel::ordered(config.queue_size);
+        let (wake_tx, wake_rx) = rtsc::channel_async::bounded(1);
+        let pool = Arc::new(Pool {
+            rx,
+            timeout: config.timeout,
+            chat_timeout: config.chat_timeout,
+            call_timeout: config.call_timeout,
+            min_workers: config.min_workers as usize,
+            max_workers: config.max_workers as usize,
+            reserved_workers: config.reserved_workers as usize,
+            idle_timeout: config.idle_timeout,
+            libraries,
+            confdir,
+            workers: <_>::default(),
+            restarts: Mutex::new(Restarts::default()),
+            delayed: <_>::default(),
+            wake_tx,
+        });
+        trace!("Starting {} PAM workers", config.min_workers);
+        for _ in 0..config.min_workers {
+            pool.spawn_worker()?;
+        }
+        let pool_weak = Arc::downgrade(&pool);
+        std::thread::Builder::new()
+            .name("PAMsupervisor".to_owned())
+            .spawn(move || supervisor(&pool_weak, &wake_rx))?;
+        Ok(Self {
+            tx,
+            admission: config.admission,
+            timeout: config.timeout,
+            pool,
+        })
+    }
+    pub(crate) fn status(&self) -> PoolStatus {
+        self.pool.status()
+    }
+    // returns an error if all workers have failed, so the request would never be processed
+    fn check_health(&self) -> Result<()> {
+        if let Some(e) = self.pool.failure() {
+            return Err(Error::NoHealthyWorkers(e));
+        }
+        Ok(())
+    }
+    fn map_timeout(&self, e: Error) -> Error {
+        if matches!(e, Error::Timeout)
+            && let Some(e) = self.pool.failure()
+        {
+            return Error::NoHealthyWorkers(e);
+        }
+        e
+    }
+    // the queue is full or the wait for a free slot has timed out
+    fn map_send_error(&self, e: rtsc::Error) -> Error {
+        match e {
+            rtsc::Error::ChannelFull | rtsc::Error::ChannelSkipped | rtsc::Error::Timeout => self
+                .pool
+                .failure()
+                .map_or(Error::Busy, Error::NoHealthyWorkers),
+            e => e.into(),
+        }
+    }
+    fn request(&self, req: Request) -> (PamAuth, PamAuthResult) {
+        let (res_tx, res_rx) = oneshot::channel();
+        let deadline = Instant::now() + self.timeout;
+        let delivery_policy = if self.admission == AdmissionPolicy::ShedOldest {
+            DeliveryPolicy::Latest
+        } else {
+            DeliveryPolicy::Always
+        };
+        let auth = PamAuth {
+            service: req.service,
+            login: req.login,
+            remote_host: req.remote_host,
+            permit: req.permit,
+            lockout: req.lockout,
+            fail_at: req.fail_at,
+            delivery_policy,
+            priority: req.priority,
+            deadline,
+            res_tx: Some(res_tx),
+        };
+        trace!(
+            "Sending PAM auth request for service '{}' and user '{}'",
+            auth.service, auth.login
+        );
+        (auth, res_rx)
+    }
+    // high-priority requests are handed over to reserved workers if all the others are busy, the
+    // request is returned if it has to be queued
+    fn reserve(&self, auth: PamAuth) -> Option<PamAuth> {
+        if auth.priority != Priority::High {
+            return Some(auth);
+        }
+        self.pool.spawn_reserved(auth)
+    }
+    #[cfg(feature = "async")]
+    pub(crate) async fn chat(&self, req: Request) -> Result<Conversation> {
+        let (auth, res_rx) = self.request(req);
+        self.check_health()?;
+        let deadline = tokio::time::Instant::from_std(auth.deadline);
+        if let Some(auth) = self.reserve(auth) {
+            if self.admission == AdmissionPolicy::Wait {
+                tokio::time::timeout_at(deadline, self.tx.send(auth))
+                    .await
+                    .map_err(|_| self.map_send_error(rtsc::Error::Timeout))?
+                    .map_err(|e| self.map_send_error(e))?;
+            } else {
+                self.tx.try_send(auth).map_err(|e| self.map_send_error(e))?;
+            }
+            self.pool.scale_up();
+        }
+        trace!("Waiting for PAM conversation");
+        tokio::time::timeout_at(deadline, res_rx)
+            .await
+            .map_err(|e| self.map_timeout(e.into()))??
+    }
+    pub(crate) fn chat_sync(&self, req: Request) -> Result<Conversation> {
+        let (auth, res_rx) = self.request(req);
+        self.check_health()?;
+        let deadline = auth.deadline;
+        if let Some(auth) = self.reserve(auth) {
+            if self.admission == AdmissionPolicy::Wait {
+                self.tx
+                    .send_blocking_timeout(auth, deadline.saturating_duration_since(Instant::now()))
+                    .map_err(|e| self.map_send_error(e))?;
+            } else {
+                self.tx.try_send(auth).map_err(|e| self.map_send_error(e))?;
+            }
+            self.pool.scale_up();
+        }
+        trace!("Waiting for PAM conversation");
+        res_rx
+            .recv_deadline(deadline)
+            .map_err(|e| self.map_timeout(e.into()))?
+    }
+}
+
+pub(crate) struct Pool {
+    rx: request_channel::Receiver<PamAuth>,
+    pub(crate) timeout: Duration,
+    pub(crate) chat_timeout: Duration,
+    call_timeout: Option<Duration>,
+    min_workers: usize,
+    max_workers: usize,
+    reserved_workers: usize,
+    idle_timeout: Duration,
+    pub(crate) libraries: Vec<PathBuf>,
+    // PAM configuration directory instead of the system one
+    pub(crate) confdir: Option<CString>,
+    workers: Mutex<Vec<Arc<Worker>>>,
+    restarts: Mutex<Restarts>,
+    delayed: Mutex<Vec<Delayed>>,
+    // wakes the supervisor up when an outcome is delayed, the supervisor exits when it is dropped
+    wake_tx: Sender<()>,
+}
+
+// a failed conversation outcome, delivered to the caller by the supervisor after the delay,
+// requested by the modules with pam_fail_delay or the minimum failure duration
+struct Delayed {
+    at: Instant,
+    msg_tx: Sender<Message>,
+    msg: Message,
+    // the concurrency limit slot is held until the outcome is delivered
+    _permit: Option<Permit>,
+}
+
+struct Restarts {
+    pending: usize,
+    // set on a failure, cleared as soon as any worker becomes ready
+    failing: bool,
+    backoff: Duration,
+    next: Instant,
+    last_error: Option<String>,
+}
+
+impl Default for Restarts {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            failing: false,
+            backoff: RESTART_BACKOFF_MIN,
+            next: Instant::now(),
+            last_error: None,
+        }
+    }
+}
+
+impl Pool {
+    fn spawn_worker(self: &Arc<Self>) -> Result<()> {
+        let worker = Arc::new(Worker::default());
+        self.workers.lock().push(worker.clone());
+        self.run_worker(&worker)
+    }
+    // the worker must be already added to the pool
+    fn run_worker(self: &Arc<Self>, worker: &Arc<Worker>) -> Result<()> {
+        let pool = self.clone();
+        let w = worker.clone();
+        if let Err(e) = std::thread::Builder::new()
+            .name("PAMworker".to_owned())
+            .spawn(move || {
+                // a panicked worker is restarted as a failed one
+                let res = panic::catch_unwind(AssertUnwindSafe(|| pam_worker(&pool, &w)))
+                    .unwrap_or_else(|_| Err(Error::Failed("PAM worker panicked".to_owned())));
+                // counted as a pending restart before removed, so the supervisor does not top
+                // the pool up twice
+                if let Err(e) = res {
+                    error!(error = ?e, "PAM worker exited with error");
+                    pool.worker_failed(&e);
+                }
+                pool.remove_worker(&w);
+            })
+        {
+            self.remove_worker(worker);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+    // spawns additional workers if there are more queued requests than idle workers, failed
+    // workers are counted as they are restarted with a backoff
+    fn scale_up(self: &Arc<Self>) {
+        let pending = self.restarts.lock().pending;
+        let (total, idle) = {
+            let workers = self.workers.lock();
+            (
+                workers.len() + pending,
+                workers.iter().filter(|w| !w.is_busy()).count(),
+            )
+        };
+        if total >= self.max_workers {
+            return;
+        }
+        let queued = self.rx.len();
+        if queued <= idle {
+            return;
+        }
+        let to_spawn = (queued - idle).min(self.max_workers - total);
+        trace!("Spawning {} additional PAM workers", to_spawn);
+        for _ in 0..to_spawn {
+            if let Err(e) = self.spawn_worker() {
+                error!(error = ?e, "Unable to spawn an additional PAM worker");
+                break;
+            }
+        }
+    }
+    // spawns a reserved worker for a high-priority request if the pool is at the maximum and all
+    // the regular workers are busy. Reserved workers are given the request directly, so they never
+    // take lower-priority requests from the queue, and exit after processing it. The request is
+    // returned back if there is no free reserved slot
+    fn spawn_reserved(self: &Arc<Self>, auth: PamAuth) -> Option<PamAuth> {
+        let worker = {
+            let mut workers = self.workers.lock();
+            let (regular, idle) = workers
+                .iter()
+                .filter(|w| !w.reserved)
+                .fold((0, 0), |(n, idle), w| {
+                    (n + 1, idle + usize::from(!w.is_busy()))
+                });
+            let reserved = workers.len() - regular;
+            if idle > 0 || regular < self.max_workers || reserved >= self.reserved_workers {
+                return Some(auth);
+            }
+            let worker = Arc::new(Worker {
+                reserved: true,
+                assigned: Mutex::new(Some(auth)),
+                ..Worker::default()
+            });
+            workers.push(worker.clone());
+            worker
+        };
+        trace!("Spawning a reserved PAM worker");
+        if let Err(e) = self.run_worker(&worker) {
+            error!(error = ?e, "Unable to spawn a reserved PAM worker");
+            return worker.assigned.lock().take();
+        }
+        None
+    }
+    // removes an idle worker from the pool if the pool is above the minimum size, reserved
+    // workers are not counted as they exit on their own
+    fn retire(&self, worker: &Arc<Worker>) -> bool {
+        let mut workers = self.workers.lock();
+        if workers.iter().filter(|w| !w.reserved).count() <= self.min_workers {
+            return false;
+        }
+        workers.retain(|w| !Arc::ptr_eq(w, worker));
+        trace!("Idle PAM worker retired, {} workers left", workers.len());
+        true
+    }
+    // spawns regular workers if the pool has fallen below the minimum size, failed workers are
+    // restarted separately with a backoff
+    fn ensure_min(self: &Arc<Self>) {
+        let pending = self.restarts.lock().pending;
+        let regular = self.workers.lock().iter().filter(|w| !w.reserved).count();
+        let missing = self.min_workers.saturating_sub(regular + pending);
+        if missing == 0 {
+            return;
+        }
+        trace!("Spawning {} PAM workers to keep the minimum", missing);
+        for _ in 0..missing {
+            if let Err(e) = self.spawn_worker() {
+                error!(error = ?e, "Unable to spawn a PAM worker");
+                break;
+            }
+        }
+    }
+    pub(crate) fn recv(&self, worker: &Arc<Worker>) -> Option<PamAuth> {
+        if worker.reserved {
+            let auth = worker.assigned.lock().take();
+            if auth.is_none() {
+                trace!("Reserved PAM worker retired");
+                self.remove_worker(worker);
+            }
+            return auth;
+        }
+        if self.max_workers <= self.min_workers {
+            return self.rx.recv_blocking().ok();
+        }
+        loop {
+            match self.rx.recv_blocking_timeout(self.idle_timeout) {
+                Ok(auth) => return Some(auth),
+                Err(rtsc::Error::Timeout) => {
+                    if self.retire(worker) {
+                        return None;
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+    // the outcome is sent by the supervisor, so the worker is free to process other requests
+    pub(crate) fn delay_outcome(
+        &self,
+        msg_tx: Sender<Message>,
+        msg: Message,
+        at: Instant,
+        permit: Option<Permit>,
+    ) {
+        trace!(delay = ?at.saturating_duration_since(Instant::now()),
+            "Delaying PAM conversation outcome");
+        self.delayed.lock().push(Delayed {
+            at,
+            msg_tx,
+            msg,
+            _permit: permit,
+        });
+        self.wake_tx.try_send(()).ok();
+    }
+    fn next_delayed(&self) -> Option<Instant> {
+        self.delayed.lock().iter().map(|d| d.at).min()
+    }
+    fn deliver_delayed(&self) {
+        let now = Instant::now();
+        let due: Vec<Delayed> = {
+            let mut delayed = self.delayed.lock();
+            if delayed.is_empty() {
+                return;
+            }
+            let (due, pending) = std::mem::take(&mut *delayed)
+                .into_iter()
+                .partition(|d| d.at <= now);
+            *delayed = pending;
+            due
+        };
+        for Delayed {
+            msg_tx,
+            msg,
+            _permit: permit,
+            ..
+        } in due
+        {
+            drop(permit);
+            if let Err(e) = msg_tx.try_send(msg) {
+                trace!(error = ?e, "Unable to deliver delayed PAM conversation outcome");
+            }
+        }
+    }
+    // stuck workers are replaced, their conversations are failed
+    fn abandon_stuck(self: &Arc<Self>) {
+        let Some(call_timeout) = self.call_timeout else {
+            return;
+        };
+        let stuck: Vec<Arc<Worker>> = self
+            .workers
+            .lock()
+            .iter()
+            .filter(|w| w.abandon_if_stuck(self, call_timeout))
+            .cloned()
+            .collect();
+        for worker in stuck {
+            self.remove_worker(&worker);
+            trace!("Spawning a replacement PAM worker");
+            if let Err(e) = self.spawn_worker() {
+                error!(error = ?e, "Unable to spawn a replacement PAM worker");
+                self.worker_failed(&e);
+            }
+        }
+    }
+    fn remove_worker(&self, worker: &Arc<Worker>) {
+        self.workers.lock().retain(|w| !Arc::ptr_eq(w, worker));
+    }
+    fn worker_failed(&self, e: &Error) {
+        let mut restarts = self.restarts.lock();
+        if restarts.pending == 0 {
+            restarts.next = Instant::now() + restarts.backoff;
+        }
+        restarts.pending += 1;
+        restarts.failing = true;
+        restarts.last_error = Some(e.to_string());
+    }
+    pub(crate) fn worker_ready(&self) {
+        let mut restarts = self.restarts.lock();
+        restarts.failing = false;
+        restarts.backoff = RESTART_BACKOFF_MIN;
+    }
+    fn restart_failed(self: &Arc<Self>) {
+        let mut restarts = self.restarts.lock();
+        if restarts.pending == 0 || restarts.next > Instant::now() {
+            return;
+        }
+        trace!("Restarting {} failed PAM workers", restarts.pending);
+        while restarts.pending > 0 {
+            if let Err(e) = self.spawn_worker() {
+                error!(error = ?e, "Unable to restart a PAM worker");
+                restarts.last_error = Some(e.to_string());
+                break;
+            }
+            restarts.pending -= 1;
+        }
+        restarts.next = Instant::now() + restarts.backoff;
+        restarts.backoff = (restarts.backoff * 2).min(RESTART_BACKOFF_MAX);
+    }
+    fn healthy_workers(&self) -> usize {
+        self.workers.lock().iter().filter(|w| w.is_ready()).count()
+    }
+    // returns the last error if no worker is able to process requests because of failures
+    fn failure(&self) -> Option<String> {
+        let restarts = self.restarts.lock();
+        if !restarts.failing || self.healthy_workers() > 0 {
+            return None;
+        }
+        restarts.last_error.clone()
+    }
+    fn status(&self) -> PoolStatus {
+        let (workers, healthy, busy) = {
+            let workers = self.workers.lock();
+            (
+                workers.len(),
+                workers.iter().filter(|w| w.is_ready()).count(),
+                workers.iter().filter(|w| w.is_busy()).count(),
+            )
+        };
+        let restarts = self.restarts.lock();
+        PoolStatus {
+            workers,
+            healthy,
+            busy,
+            queued: self.rx.len(),
+            restarts_pending: restarts.pending,
+            last_error: restarts.last_error.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum PamCall {
+    Start,
+    Authenticate,
+    AcctMgmt,
+}
+
+#[derive(Default)]
+struct WorkerState {
+    call: Option<PamCall>,
+    call_started: Option<Instant>,
+    msg_tx: Option<Sender<Message>>,
+    // kept until the conversation is passed to the caller, so the watchdog can answer it
+    res_tx: Option<oneshot::Sender<Result<Conversation>>>,
+    // the concurrency limit slot, released by the watchdog if the worker is abandoned
+    permit: Option<Permit>,
+    fail_at: Option<Instant>,
+}
+
+#[derive(Default)]
+pub(crate) struct Worker {
+    state: Mutex<WorkerState>,
+    reserved: bool,
+    // the request of a reserved worker
+    assigned: Mutex<Option<PamAuth>>,
+    ready: AtomicBool,
+    abandoned: AtomicBool,
+}
+
+impl Worker {
+    pub(crate) fn set_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+    fn is_busy(&self) -> bool {
+        self.state.lock().msg_tx.is_some()
+    }
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire) && !self.is_abandoned()
+    }
+    pub(crate) fn begin(&self, msg_tx: Sender<Message>, auth: &mut PamAuth) {
+        let mut state = self.state.lock();
+        state.msg_tx = Some(msg_tx);
+        state.res_tx = auth.res_tx.take();
+        state.permit = auth.permit.take();
+        state.fail_at = auth.fail_at;
+    }
+    // passes the conversation (or an error) to the caller, unless already answered by the
+    // watchdog, returns false if the caller is gone
+    pub(crate) fn respond(&self, res: Result<Conversation>) -> bool {
+        self.state
+            .lock()
+            .res_tx
+            .take()
+            .is_some_and(|res_tx| res_tx.send(res).is_ok())
+    }
+    pub(crate) fn enter(&self, call: PamCall) {
+        let mut state = self.state.lock();
+        state.call = Some(call);
+        state.call_started = Some(Instant::now());
+    }
+    // returns true if the worker has been abandoned by the watchdog
+    pub(crate) fn leave(&self) -> bool {
+        let mut state = self.state.lock();
+        state.call = None;
+        state.call_started = None;
+        self.is_abandoned()
+    }
+    // returns the concurrency limit slot, to be released when the outcome is delivered
+    pub(crate) fn finish(&self) -> Option<Permit> {
+        let mut state = self.state.lock();
+        state.msg_tx.take();
+        state.permit.take()
+    }
+    // the time spent on waiting for the client is not counted
+    pub(crate) fn pause(&self) -> WorkerPause<'_> {
+        self.state.lock().call_started = None;
+        WorkerPause(self)
+    }
+    fn resume(&self) {
+        let mut state = self.state.lock();
+        if state.call.is_some() {
+            state.call_started = Some(Instant::now());
+        }
+    }
+    pub(crate) fn is_abandoned(&self) -> bool {
+        self.abandoned.load(Ordering::Acquire)
+    }
+    fn abandon_if_stuck(&self, pool: &Pool, call_timeout: Duration) -> bool {
+        let mut state = self.state.lock();
+        let (Some(call), Some(started)) = (state.call, state.call_started) else {
+            return false;
+        };
+        if started.elapsed() < call_timeout {
+            return false;
+        }
+        self.abandoned.store(true, Ordering::Release);
+        warn!(
+            ?call,
+            elapsed = ?started.elapsed(),
+            "PAM worker is stuck in a PAM call, abandoning"
+        );
+        let msg_tx = state.msg_tx.take();
+        drop(state.permit.take());
+        if let Some(res_tx) = state.res_tx.take() {
+            // stuck before the conversation has been passed to the caller
+            res_tx
+                .send(Err(Error::Failed("PAM call timed out".to_owned())))
+                .ok();
+        } else if let Some(msg_tx) = msg_tx {
+            let msg = if call == PamCall::AcctMgmt {
+                Message::ValidationFailed
+            } else {
+                Message::AuthenticationFailed
+            };
+            match state.fail_at {
+                Some(at) if at > Instant::now() => pool.delay_outcome(msg_tx, msg, at, None),
+                _ => {
+                    msg_tx.try_send(msg).ok();
+                }
+            }
+        }
+        true
+    }
+}
+
+pub(crate) struct WorkerPause<'a>(&'a Worker);
+
+impl Drop for WorkerPause<'_> {
+    fn drop(&mut self) {
+        self.0.resume();
+    }
+}
+
+fn supervisor(pool: &Weak<Pool>, wake_rx: &Receiver<()>) {
+    trace!("Starting PAM supervisor thread");
+    let mut next_tick = Instant::now() + SUPERVISOR_INTERVAL;
+    loop {
+        // delayed outcomes are delivered at their time, the workers are checked on ticks
+        let Some(until) = pool.upgrade().map(|pool| {
+            pool.next_delayed()
+                .map_or(next_tick, |at| at.min(next_tick))
+        }) else {
+            break;
+        };
+        match wake_rx.recv_blocking_timeout(until.saturating_duration_since(Instant::now())) {
+            Ok(()) | Err(rtsc::Error::Timeout) => {}
+            Err(_) => break,
+        }
+        let Some(pool) = pool.upgrade() else {
+            break;
+        };
+        pool.deliver_delayed();
+        if Instant::now() < next_tick {
+            continue;
+        }
+        next_tick = Instant::now() + SUPERVISOR_INTERVAL;
+        pool.abandon_stuck();
+        pool.restart_failed();
+        pool.ensure_min();
+        pool.scale_up();
+    }
+    trace!("PAM supervisor thread exiting");
+}
@@ -0,0 +1,251 @@
+use crate::Message;
+
+// the keyword lists cover the prompts of common Linux-PAM modules (pam_unix, pam_pwquality,
+// pam_sss, pam_krb5, OTP modules) and the Linux-PAM translations, the texts are lowercased, some
+// keywords are word stems to match the inflected forms
+const OTP: &[&str] = &[
+    "verification code",
+    "one-time",
+    "one time",
+    "otp",
+    "passcode",
+    "token",
+    "oath",
+    "yubikey",
+    "second factor",
+    "2fa",
+    "authenticator code",
+    "security code",
+    "bestätigungscode",
+    "einmalpasswort",
+    "code de vérification",
+    "código de verificación",
+    "código de verificação",
+    "codice di verifica",
+    "kod weryfikacyjny",
+    "код подтверждения",
+    "одноразов",
+    "код підтвердження",
+    "确认码",
+    "验证码",
+    "驗證碼",
+    "確認コード",
+    "인증 코드",
+];
+
+const PASSWORD: &[&str] = &[
+    "password",
+    "first factor",
+    "passwort",
+    "kennwort",
+    "mot de passe",
+    "contraseña",
+    "senha",
+    "парол",
+    "hasł",
+    "wachtwoord",
+    "hesl",
+    "lösenord",
+    "adgangskode",
+    "passord",
+    "salasana",
+    "parola",
+    "jelszó",
+    "parolă",
+    "κωδικός",
+    "パスワード",
+    "密码",
+    "密碼",
+    "口令",
+    "암호",
+    "비밀번호",
+];
+
+const RETYPE: &[&str] = &[
+    "retype",
+    "re-type",
+    "reenter",
+    "re-enter",
+    "again",
+    "confirm",
+    "repeat",
+    "verify",
+    "wiederholen",
+    "erneut",
+    "retapez",
+    "confirmez",
+    "vuelva a",
+    "repita",
+    "redigite",
+    "novamente",
+    "reimmettere",
+    "ripeti",
+    "повтор",
+    "ponownie",
+    "powtórz",
+    "herha",
+    "opakujte",
+    "igen",
+    "gentag",
+    "uudelleen",
+    "tekrar",
+    "再入力",
+    "重新输入",
+    "再次输入",
+    "再次輸入",
+    "重新輸入",
+    "재입력",
+    "다시",
+];
+
+const NEW: &[&str] = &[
+    "new", "neue", "nouveau", "nueva", "nova", "nuova", "нов", "nowe", "nieuw", "nové", "nytt",
+    "uusi", "yeni", "新", "새",
+];
+
+const CURRENT: &[&str] = &[
+    "current",
+    "old",
+    "aktuell",
+    "alte",
+    "actuel",
+    "ancien",
+    "actual",
+    "atual",
+    "attuale",
+    "текущ",
+    "старый",
+    "поточн",
+    "bieżące",
+    "obecne",
+    "huidig",
+    "současné",
+    "nuvarande",
+    "nykyinen",
+    "mevcut",
+    "現在",
+    "当前",
+    "目前",
+    "현재",
+];
+
+const USERNAME: &[&str] = &[
+    "login",
+    "username",
+    "user name",
+    "user id",
+    "userid",
+    "benutzername",
+    "anmeldename",
+    "nom d'utilisateur",
+    "identifiant",
+    "usuario",
+    "usuário",
+    "nome utente",
+    "логин",
+    "имя пользователя",
+    "ім'я користувача",
+    "użytkownik",
+    "gebruikersnaam",
+    "uživatelské jméno",
+    "användarnamn",
+    "käyttäjätunnus",
+    "kullanıcı adı",
+    "ユーザー名",
+    "用户名",
+    "使用者名稱",
+    "사용자 이름",
+];
+
+// words, followed by a user or a principal name, e.g. "Password for bob@EXAMPLE.COM: "
+const NAME_PREFIXES: &[&str] = &[
+    "for", "für", "pour", "para", "per", "voor", "för", "dla", "для", "pro",
+];
+
+/// The kind of a prompt, detected by the prompt text, see [`PromptKind::classify`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PromptKind {
+    Password,
+    NewPassword,
+    RetypeNewPassword,
+    /// The current password, asked when the password is changed
+    CurrentPassword,
+    /// A one-time password or a verification code
+    Otp,
+    Username,
+    Unknown,
+}
+
+impl PromptKind {
+    /// Classifies a prompt by the text. Prompts of the common Linux-PAM modules and their
+    /// translations are recognized, the others are reported as [`PromptKind::Unknown`]
+    pub fn classify(prompt: &str) -> Self {
+        let prompt = strip_names(&prompt.to_lowercase());
+        let has = |keywords: &[&str]| keywords.iter().any(|k| contains_word(&prompt, k));
+        // one-time passwords are checked first as they are often called "passwords" as well
+        if has(OTP) {
+            PromptKind::Otp
+        } else if has(PASSWORD) {
+            if has(RETYPE) {
+                PromptKind::RetypeNewPassword
+            } else if has(NEW) {
+                PromptKind::NewPassword
+            } else if has(CURRENT) {
+                PromptKind::CurrentPassword
+            } else {
+                PromptKind::Password
+            }
+        } else if has(USERNAME) {
+            PromptKind::Username
+        } else {
+            PromptKind::Unknown
+        }
+    }
+    pub fn is_password(self) -> bool {
+        matches!(
+            self,
+            PromptKind::Password
+                | PromptKind::NewPassword
+                | PromptKind::RetypeNewPassword
+                | PromptKind::CurrentPassword
+        )
+    }
+}
+
+// user and principal names may contain keywords (e.g. "newman@REALM"), so the words after the
+// name prefixes and the ones with "@" are removed
+fn strip_names(prompt: &str) -> String {
+    let mut stripped = String::with_capacity(prompt.len());
+    let mut name_next = false;
+    for word in prompt.split_whitespace() {
+        let is_name = name_next || word.contains('@');
+        name_next = NAME_PREFIXES.contains(&word);
+        if !is_name {
+            stripped.push_str(word);
+            stripped.push(' ');
+        }
+    }
+    stripped
+}
+
+// the keyword must start a word (e.g. "new" does not match "andrew@REALM"), except CJK ones as the
+// words are not separated
+fn contains_word(text: &str, keyword: &str) -> bool {
+    let cjk = keyword.starts_with(|c: char| c >= '\u{2e80}');
+    text.match_indices(keyword).any(|(pos, _)| {
+        cjk || !text[..pos]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric)
+    })
+}
+
+impl Message {
+    /// The prompt kind, `None` if the message is not a prompt
+    pub fn prompt_kind(&self) -> Option<PromptKind> {
+        match self {
+            Message::Echo(prompt) | Message::NoEcho(prompt) => Some(PromptKind::classify(prompt)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use rtsc::Error as ChannelError;
+use tracing::trace;
+
+use crate::{Conversation, ConversationPeer, Message};
+
+// how often the relay checks if the backend is still alive while waiting for an answer
+const ANSWER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// the message is used by the transcript recorder only
+#[cfg_attr(not(feature = "transcript"), allow(dead_code))]
+pub(crate) enum Relayed<'a> {
+    Message(&'a Message),
+    Answer,
+}
+
+impl Message {
+    pub(crate) fn is_outcome(&self) -> bool {
+        matches!(
+            self,
+            Message::Authenticated | Message::AuthenticationFailed | Message::ValidationFailed
+        )
+    }
+    pub(crate) fn is_prompt(&self) -> bool {
+        matches!(self, Message::Echo(_) | Message::NoEcho(_))
+    }
+}
+
+// relays the messages of the inner conversation to the peer and the answers back, the outcome is
+// returned to the caller and not sent, `None` is returned if either side has gone
+pub(crate) fn relay<F>(
+    inner: &Conversation,
+    peer: &ConversationPeer,
+    mut observe: F,
+) -> Option<Message>
+where
+    F: FnMut(Relayed<'_>),
+{
+    while let Ok(msg) = inner.rx().recv_blocking() {
+        observe(Relayed::Message(&msg));
+        if msg.is_outcome() {
+            return Some(msg);
+        }
+        let prompt = msg.is_prompt();
+        if peer.tx().send_blocking(msg).is_err() {
+            trace!("The client has abandoned the relayed conversation");
+            return None;
+        }
+        if !prompt {
+            continue;
+        }
+        loop {
+            match peer.rx().recv_blocking_timeout(ANSWER_POLL_INTERVAL) {
+                Ok(answer) => {
+                    observe(Relayed::Answer);
+                    inner.tx().send_blocking(answer).ok();
+                    break;
+                }
+                // the backend has timed out, the outcome is still expected
+                Err(ChannelError::Timeout) if !inner.tx().is_alive() => break,
+                Err(ChannelError::Timeout) => {}
+                Err(_) => {
+                    trace!("The client has abandoned the relayed conversation");
+                    return None;
+                }
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,122 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicI32, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use rtsc::channel_async::{Receiver, Sender};
+
+use crate::{Conversation, Error, Message, Result};
+
+type RecvFuture = Pin<Box<dyn Future<Output = rtsc::Result<Message>> + Send>>;
+type SendFuture = Pin<Box<dyn Future<Output = rtsc::Result<()>> + Send>>;
+
+/// The receiving half of a conversation (see [`Conversation::split`]), a stream of the messages.
+/// The stream ends after the final outcome or when the backend side is closed
+pub struct MessageStream {
+    msg_rx: Receiver<Message>,
+    failure_code: Arc<AtomicI32>,
+    // the pending future owns a receiver clone, so the stream is not self-referential
+    pending: Option<RecvFuture>,
+    finished: bool,
+}
+
+impl MessageStream {
+    /// See [`Conversation::failure_code`]
+    pub fn failure_code(&self) -> Option<i32> {
+        let rc = self.failure_code.load(Ordering::Relaxed);
+        (rc != 0).then_some(rc)
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        if self.pending.is_none() {
+            let msg_rx = self.msg_rx.clone();
+            self.pending = Some(Box::pin(async move { msg_rx.recv().await }));
+        }
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let res = ready!(pending.as_mut().poll(cx));
+        self.pending = None;
+        let Ok(msg) = res else {
+            self.finished = true;
+            return Poll::Ready(None);
+        };
+        if msg.is_outcome() {
+            self.finished = true;
+        }
+        Poll::Ready(Some(msg))
+    }
+}
+
+/// The sending half of a conversation (see [`Conversation::split`]), a sink of the prompt answers
+pub struct AnswerSink {
+    input_tx: Option<Sender<String>>,
+    pending: Option<SendFuture>,
+}
+
+impl AnswerSink {
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let res = ready!(pending.as_mut().poll(cx));
+        self.pending = None;
+        Poll::Ready(res.map_err(Into::into))
+    }
+}
+
+impl Sink<String> for AnswerSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_pending(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, answer: String) -> Result<()> {
+        let Some(input_tx) = self.input_tx.clone() else {
+            return Err(Error::Failed("the sink is closed".to_owned()));
+        };
+        self.pending = Some(Box::pin(async move { input_tx.send(answer).await }));
+        Ok(())
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_pending(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        self.input_tx = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Conversation {
+    /// Splits the conversation into a stream of messages and a sink of answers, e.g. to use them
+    /// with `StreamExt`/`SinkExt` combinators or forward to a websocket
+    pub fn split(self) -> (MessageStream, AnswerSink) {
+        (
+            MessageStream {
+                msg_rx: self.msg_rx,
+                failure_code: self.failure_code,
+                pending: None,
+                finished: false,
+            },
+            AnswerSink {
+                input_tx: Some(self.input_tx),
+                pending: None,
+            },
+        )
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs::File,
+    io::Write,
+    mem::MaybeUninit,
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use libc::c_int;
+use rtsc::Error as ChannelError;
+use rtsc::locking::Mutex;
+
+use crate::{Conversation, Error, Message, Result};
+
+const TTY: &str = "/dev/tty";
+// how often Ctrl-C is checked while waiting for the backend or the user
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_signum: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// the handler is shared by concurrent terminal conversations, the previous one is restored when
+// the last of them is finished
+struct Sigint {
+    users: usize,
+    previous: Option<libc::sigaction>,
+}
+
+static SIGINT: Mutex<Sigint> = Mutex::new(Sigint {
+    users: 0,
+    previous: None,
+});
+
+struct SigintGuard;
+
+impl SigintGuard {
+    fn install() -> Result<Self> {
+        let mut sigint = SIGINT.lock();
+        if sigint.users == 0 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_sigint as *const () as libc::sighandler_t;
+            // no SA_RESTART, the blocking terminal read is interrupted
+            action.sa_flags = 0;
+            unsafe { libc::sigemptyset(&raw mut action.sa_mask) };
+            let mut previous = MaybeUninit::<libc::sigaction>::uninit();
+            if unsafe { libc::sigaction(libc::SIGINT, &raw const action, previous.as_mut_ptr()) }
+                != 0
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            sigint.previous = Some(unsafe { previous.assume_init() });
+        }
+        sigint.users += 1;
+        Ok(Self)
+    }
+}
+
+impl Drop for SigintGuard {
+    fn drop(&mut self) {
+        let mut sigint = SIGINT.lock();
+        sigint.users -= 1;
+        if sigint.users == 0
+            && let Some(previous) = sigint.previous.take()
+        {
+            unsafe { libc::sigaction(libc::SIGINT, &raw const previous, std::ptr::null_mut()) };
+        }
+    }
+}
+
+// terminal echo is restored on drop
+struct EchoGuard {
+    fd: c_int,
+    previous: libc::termios,
+}
+
+impl EchoGuard {
+    fn disable(fd: c_int) -> Result<Self> {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let previous = unsafe { termios.assume_init() };
+        let mut termios = previous;
+        termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw const termios) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self { fd, previous })
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSAFLUSH, &raw const self.previous) };
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// reads a line, Ctrl-C and end of input cancel the conversation. The terminal is polled as the
+// signal may be delivered to any thread of the process, so the read is not always interrupted
+fn read_line(tty: &File) -> Result<String> {
+    let fd = tty.as_raw_fd();
+    let mut line = Vec::new();
+    loop {
+        if interrupted() {
+            return Err(Error::Cancelled);
+        }
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = c_int::try_from(POLL_INTERVAL.as_millis()).unwrap_or(c_int::MAX);
+        match unsafe { libc::poll(&raw mut pollfd, 1, timeout) } {
+            0 => continue,
+            rc if rc < 0 => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e.into());
+            }
+            _ => {}
+        }
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(fd, (&raw mut byte).cast(), 1) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+        if n == 0 {
+            if line.is_empty() {
+                return Err(Error::Cancelled);
+            }
+            break;
+        }
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+    }
+    let line = String::from_utf8(line).map_err(Error::access)?;
+    Ok(line
+        .strip_suffix('\r')
+        .map(ToOwned::to_owned)
+        .unwrap_or(line))
+}
+
+fn prompt(mut tty: &File, text: &str, echo: bool) -> Result<String> {
+    write!(tty, "{}", text)?;
+    tty.flush()?;
+    let answer = if echo {
+        read_line(tty)
+    } else {
+        let _guard = EchoGuard::disable(tty.as_raw_fd())?;
+        read_line(tty)
+    };
+    // the new line is not echoed
+    if !echo || answer.is_err() {
+        writeln!(tty)?;
+    }
+    answer
+}
+
+impl Conversation {
+    /// Runs the conversation on the controlling terminal: info and error messages are printed,
+    /// the prompts are answered by the user, the password ones are read with the echo disabled.
+    ///
+    /// Returns `true` if the user has been authenticated and validated. Ctrl-C (or the end of
+    /// input) cancels the conversation with [`Error::Cancelled`], the SIGINT handler of the process
+    /// is replaced while any terminal conversation is running
+    pub fn run_terminal(&self) -> Result<bool> {
+        let mut tty = File::options().read(true).write(true).open(TTY)?;
+        let _sigint = SigintGuard::install()?;
+        loop {
+            if interrupted() {
+                writeln!(tty)?;
+                return Err(Error::Cancelled);
+            }
+            let msg = match self.rx().recv_blocking_timeout(POLL_INTERVAL) {
+                Ok(msg) => msg,
+                Err(ChannelError::Timeout) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match msg {
+                Message::Echo(text) => self.tx().send_blocking(prompt(&tty, &text, true)?)?,
+                Message::NoEcho(text) => self.tx().send_blocking(prompt(&tty, &text, false)?)?,
+                Message::Info(text) | Message::Error(text) => writeln!(tty, "{}", text)?,
+                Message::Authenticated => return Ok(true),
+                Message::AuthenticationFailed | Message::ValidationFailed => return Ok(false),
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use tracing::trace;
+
+use crate::{
+    Authenticator, AuthenticatorBuilder, Backend, Conversation, ConversationPeer, Message, Request,
+    Result,
+};
+
+const DEFAULT_CHAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const PAM_AUTH_ERR: i32 = 7;
+const PAM_USER_UNKNOWN: i32 = 10;
+const PAM_ACCT_EXPIRED: i32 = 13;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Prompt {
+        echo: bool,
+        prompt: String,
+        expected: Option<String>,
+    },
+    Info(String),
+    Error(String),
+    Delay(Duration),
+}
+
+/// A scripted user: messages are sent to the client one by one, the answers are checked against
+/// the expected ones. A wrong answer fails the authentication stage
+#[derive(Debug, Clone, Default)]
+pub struct MockUser {
+    steps: Vec<Step>,
+    validation_failed: bool,
+    failure_code: Option<i32>,
+}
+
+impl MockUser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A typical user, asked for the password with `Password: ` prompt
+    pub fn password(password: &str) -> Self {
+        Self::new().no_echo("Password: ", password)
+    }
+    /// A prompt, the answer of which is displayed, e.g. a login or an OTP code
+    pub fn echo(mut self, prompt: &str, expected: &str) -> Self {
+        self.steps.push(Step::Prompt {
+            echo: true,
+            prompt: prompt.to_owned(),
+            expected: Some(expected.to_owned()),
+        });
+        self
+    }
+    /// A prompt, the answer of which is hidden, e.g. a password
+    pub fn no_echo(mut self, prompt: &str, expected: &str) -> Self {
+        self.steps.push(Step::Prompt {
+            echo: false,
+            prompt: prompt.to_owned(),
+            expected: Some(expected.to_owned()),
+        });
+        self
+    }
+    /// A prompt, any answer is accepted
+    pub fn prompt(mut self, prompt: &str, echo: bool) -> Self {
+        self.steps.push(Step::Prompt {
+            echo,
+            prompt: prompt.to_owned(),
+            expected: None,
+        });
+        self
+    }
+    pub fn info(mut self, text: &str) -> Self {
+        self.steps.push(Step::Info(text.to_owned()));
+        self
+    }
+    pub fn error(mut self, text: &str) -> Self {
+        self.steps.push(Step::Error(text.to_owned()));
+        self
+    }
+    /// A pause before the next step, e.g. a slow module or a network call
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Delay(delay));
+        self
+    }
+    /// The account management stage fails ([`Message::ValidationFailed`]) after the
+    /// authentication is passed
+    pub fn validation_failed(mut self) -> Self {
+        self.validation_failed = true;
+        self
+    }
+    /// The code, reported by [`Conversation::failure_code`] on failures (default: `PAM_AUTH_ERR`)
+    pub fn failure_code(mut self, code: i32) -> Self {
+        self.failure_code = Some(code);
+        self
+    }
+}
+
+/// An in-memory backend, which runs scripted users, no libpam and no system users are required.
+/// Unknown users are asked for a password and fail the authentication
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    users: BTreeMap<String, MockUser>,
+    services: BTreeMap<(String, String), MockUser>,
+    chat_timeout: Option<Duration>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A user, available for all services
+    pub fn user(mut self, login: &str, user: MockUser) -> Self {
+        self.users.insert(login.to_owned(), user);
+        self
+    }
+    /// A user, available for the specified service only (overrides the one set with
+    /// [`MockBackend::user`])
+    pub fn service_user(mut self, service: &str, login: &str, user: MockUser) -> Self {
+        self.services
+            .insert((service.to_owned(), login.to_owned()), user);
+        self
+    }
+    /// Time to wait for a client answer (default: 60s)
+    pub fn chat_timeout(mut self, chat_timeout: Duration) -> Self {
+        self.chat_timeout = Some(chat_timeout);
+        self
+    }
+    /// Creates an authenticator with the mock backend and the default settings
+    pub fn authenticator(self) -> Authenticator {
+        Authenticator::from_backend(self)
+    }
+    /// Creates an authenticator with the mock backend and the builder settings (limits, lockout,
+    /// minimum failure duration etc.), the pool and the library settings are ignored
+    pub fn build(self, builder: AuthenticatorBuilder) -> Result<Authenticator> {
+        builder.build_with(self)
+    }
+    fn script(&self, service: &str, login: &str) -> MockUser {
+        self.services
+            .get(&(service.to_owned(), login.to_owned()))
+            .or_else(|| self.users.get(login))
+            .cloned()
+            .unwrap_or_else(|| {
+                MockUser::new()
+                    .prompt("Password: ", false)
+                    .failure_code(PAM_USER_UNKNOWN)
+            })
+    }
+}
+
+impl Backend for MockBackend {
+    fn chat_sync(&self, request: Request) -> Result<Conversation> {
+        let user = self.script(request.service(), request.login());
+        let known = self.users.contains_key(request.login())
+            || self
+                .services
+                .contains_key(&(request.service().to_owned(), request.login().to_owned()));
+        let chat_timeout = self.chat_timeout.unwrap_or(DEFAULT_CHAT_TIMEOUT);
+        let (c, peer) = request.conversation();
+        std::thread::Builder::new()
+            .name("PAMmock".to_owned())
+            .spawn(move || {
+                trace!(
+                    "Starting mock conversation for user '{}', service '{}'",
+                    request.login(),
+                    request.service()
+                );
+                run(&peer, &user, known, chat_timeout);
+                // the request (and the concurrency limit slot) is held until the end
+                drop(request);
+            })?;
+        Ok(c)
+    }
+}
+
+fn run(peer: &ConversationPeer, user: &MockUser, known: bool, chat_timeout: Duration) {
+    let mut passed = known;
+    for step in &user.steps {
+        match step {
+            Step::Prompt {
+                echo,
+                prompt,
+                expected,
+            } => {
+                let msg = if *echo {
+                    Message::Echo(prompt.clone())
+                } else {
+                    Message::NoEcho(prompt.clone())
+                };
+                if peer.tx().send_blocking(msg).is_err() {
+                    return;
+                }
+                let Ok(answer) = peer.rx().recv_blocking_timeout(chat_timeout) else {
+                    trace!("No answer from the client, aborting the mock conversation");
+                    passed = false;
+                    break;
+                };
+                if expected.as_ref().is_some_and(|e| *e != answer) {
+                    passed = false;
+                    break;
+                }
+            }
+            Step::Info(text) => {
+                if peer
+                    .tx()
+                    .send_blocking(Message::Info(text.clone()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Step::Error(text) => {
+                if peer
+                    .tx()
+                    .send_blocking(Message::Error(text.clone()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Step::Delay(delay) => std::thread::sleep(*delay),
+        }
+    }
+    let outcome = if !passed {
+        peer.set_failure_code(user.failure_code.unwrap_or(PAM_AUTH_ERR));
+        Message::AuthenticationFailed
+    } else if user.validation_failed {
+        peer.set_failure_code(user.failure_code.unwrap_or(PAM_ACCT_EXPIRED));
+        Message::ValidationFailed
+    } else {
+        Message::Authenticated
+    };
+    peer.send_outcome(outcome).ok();
+}
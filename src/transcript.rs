@@ -0,0 +1,202 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::relay::{Relayed, relay};
+use crate::{
+    Authenticator, AuthenticatorBuilder, Backend, Conversation, ConversationPeer, Message, Request,
+    Result,
+};
+
+const DEFAULT_CHAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const PAM_CONV_ERR: i32 = 19;
+
+pub(crate) type Recorder = Arc<dyn Fn(Transcript) + Send + Sync>;
+
+/// A recorded conversation. Messages are kept as-is, client answers are redacted
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub service: String,
+    pub login: String,
+    pub events: Vec<Event>,
+    /// See [`Conversation::failure_code`]
+    pub failure_code: Option<i32>,
+}
+
+/// A transcript event, the time is counted in milliseconds since the conversation start
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    Message {
+        elapsed_ms: u64,
+        message: Message,
+    },
+    /// A client answer, the text is not recorded
+    Answer {
+        elapsed_ms: u64,
+    },
+}
+
+impl Event {
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            Event::Message { elapsed_ms, .. } | Event::Answer { elapsed_ms } => {
+                Duration::from_millis(*elapsed_ms)
+            }
+        }
+    }
+}
+
+impl Transcript {
+    /// The final outcome, if the conversation has been finished
+    pub fn outcome(&self) -> Option<&Message> {
+        self.events.iter().rev().find_map(|e| match e {
+            Event::Message { message, .. } if message.is_outcome() => Some(message),
+            _ => None,
+        })
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+impl Conversation {
+    /// Records the conversation: the returned one relays the messages and the answers, the
+    /// handler is called with the transcript when the conversation is finished (or abandoned by
+    /// either side)
+    pub fn record<F>(self, service: &str, login: &str, handler: F) -> Result<Conversation>
+    where
+        F: FnOnce(Transcript) + Send + 'static,
+    {
+        let mut transcript = Transcript {
+            service: service.to_owned(),
+            login: login.to_owned(),
+            ..Transcript::default()
+        };
+        let (c, peer) = Conversation::pair();
+        std::thread::Builder::new()
+            .name("PAMrecord".to_owned())
+            .spawn(move || {
+                record(&self, &peer, &mut transcript);
+                handler(transcript);
+            })?;
+        Ok(c)
+    }
+}
+
+fn record(inner: &Conversation, peer: &ConversationPeer, transcript: &mut Transcript) {
+    let started = Instant::now();
+    let outcome = relay(inner, peer, |relayed| {
+        let elapsed_ms = elapsed_ms(started);
+        transcript.events.push(match relayed {
+            Relayed::Message(msg) => Event::Message {
+                elapsed_ms,
+                message: msg.clone(),
+            },
+            Relayed::Answer => Event::Answer { elapsed_ms },
+        });
+    });
+    if let Some(msg) = outcome {
+        transcript.failure_code = inner.failure_code();
+        if let Some(code) = transcript.failure_code {
+            peer.set_failure_code(code);
+        }
+        peer.tx().send_blocking(msg).ok();
+    }
+}
+
+/// Plays a recorded transcript back for all requests: the messages are sent to the client, the
+/// recorded answers are awaited (the answer texts are not checked), the recorded failure code is
+/// reported. If the client does not answer, the conversation fails with `PAM_CONV_ERR`
+#[derive(Clone)]
+pub struct ReplayBackend {
+    transcript: Arc<Transcript>,
+    timing: bool,
+    chat_timeout: Option<Duration>,
+}
+
+impl ReplayBackend {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            transcript: Arc::new(transcript),
+            timing: false,
+            chat_timeout: None,
+        }
+    }
+    /// Reproduces the recorded timing of the messages (default: the messages are sent at once)
+    pub fn timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+    /// Time to wait for a client answer (default: 60s)
+    pub fn chat_timeout(mut self, chat_timeout: Duration) -> Self {
+        self.chat_timeout = Some(chat_timeout);
+        self
+    }
+    /// Creates an authenticator with the replay backend and the default settings
+    pub fn authenticator(self) -> Authenticator {
+        Authenticator::from_backend(self)
+    }
+    /// Creates an authenticator with the replay backend and the builder settings
+    pub fn build(self, builder: AuthenticatorBuilder) -> Result<Authenticator> {
+        builder.build_with(self)
+    }
+}
+
+impl Backend for ReplayBackend {
+    fn chat_sync(&self, request: Request) -> Result<Conversation> {
+        let transcript = self.transcript.clone();
+        let timing = self.timing;
+        let chat_timeout = self.chat_timeout.unwrap_or(DEFAULT_CHAT_TIMEOUT);
+        let (c, peer) = request.conversation();
+        std::thread::Builder::new()
+            .name("PAMreplay".to_owned())
+            .spawn(move || {
+                trace!(
+                    "Replaying conversation for user '{}', service '{}'",
+                    request.login(),
+                    request.service()
+                );
+                replay(&peer, &transcript, timing, chat_timeout);
+                // the request (and the concurrency limit slot) is held until the end
+                drop(request);
+            })?;
+        Ok(c)
+    }
+}
+
+fn replay(peer: &ConversationPeer, transcript: &Transcript, timing: bool, chat_timeout: Duration) {
+    let started = Instant::now();
+    for event in &transcript.events {
+        match event {
+            Event::Message { message, .. } => {
+                if timing && let Some(d) = event.elapsed().checked_sub(started.elapsed()) {
+                    std::thread::sleep(d);
+                }
+                if message.is_outcome() {
+                    if let Some(code) = transcript.failure_code {
+                        peer.set_failure_code(code);
+                    }
+                    peer.send_outcome(message.clone()).ok();
+                    return;
+                }
+                if peer.tx().send_blocking(message.clone()).is_err() {
+                    return;
+                }
+            }
+            Event::Answer { .. } => {
+                if peer.rx().recv_blocking_timeout(chat_timeout).is_err() {
+                    trace!("No answer from the client, aborting the replay");
+                    peer.set_failure_code(PAM_CONV_ERR);
+                    peer.send_outcome(Message::AuthenticationFailed).ok();
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// Custom backend tests
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "testing")]
+use easypam::testing::{MockBackend, MockUser};
+use easypam::{
+    AuthenticatorBuilder, Backend, Conversation, Error, LockoutConfig, Message, Request, Result,
+};
+
+// a password prompt, the password is "xxx"
+struct Simple;
+
+impl Backend for Simple {
+    fn chat_sync(&self, request: Request) -> Result<Conversation> {
+        let (c, peer) = Conversation::pair();
+        std::thread::spawn(move || {
+            if peer
+                .tx()
+                .send_blocking(Message::NoEcho("Password: ".to_owned()))
+                .is_err()
+            {
+                return;
+            }
+            let Ok(password) = peer.rx().recv_blocking() else {
+                return;
+            };
+            drop(request);
+            if password == "xxx" {
+                peer.tx().send_blocking(Message::Authenticated).ok();
+            } else {
+                peer.set_failure_code(7);
+                peer.tx().send_blocking(Message::AuthenticationFailed).ok();
+            }
+        });
+        Ok(c)
+    }
+}
+
+// the same, the outcomes are sent with the request policy applied by the backend
+struct Direct;
+
+impl Backend for Direct {
+    fn chat_sync(&self, request: Request) -> Result<Conversation> {
+        let (c, peer) = request.conversation();
+        std::thread::spawn(move || {
+            if peer
+                .tx()
+                .send_blocking(Message::NoEcho("Password: ".to_owned()))
+                .is_err()
+            {
+                return;
+            }
+            let Ok(password) = peer.rx().recv_blocking() else {
+                return;
+            };
+            if password == "xxx" {
+                peer.send_outcome(Message::Authenticated).ok();
+            } else {
+                peer.set_failure_code(7);
+                peer.send_outcome(Message::AuthenticationFailed).ok();
+            }
+        });
+        Ok(c)
+    }
+}
+
+// the relay threads of the tests with the simple backend are checked by name
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn has_thread(name: &str) -> bool {
+    std::fs::read_dir("/proc/self/task").unwrap().any(|task| {
+        std::fs::read_to_string(task.unwrap().path().join("comm"))
+            .is_ok_and(|comm| comm.trim_end() == name)
+    })
+}
+
+#[test]
+fn lockout() {
+    let _serial = SERIAL.lock().unwrap();
+    let auth = AuthenticatorBuilder::new()
+        .lockout(
+            LockoutConfig::new()
+                .threshold(2)
+                .lockout(Duration::from_secs(10)),
+        )
+        .build_with(Simple)
+        .unwrap();
+    let host = auth.with_remote_host("10.0.0.1");
+    assert!(!host.authenticate_sync("test", "bob", "wrong").unwrap());
+    // a success resets the login counter
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    // the failure code is passed through
+    let c = host.chat_sync("test", "alice").unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::NoEcho("Password: ".to_owned())
+    );
+    c.tx().send_blocking("wrong".to_owned()).unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::AuthenticationFailed
+    );
+    assert_eq!(c.failure_code(), Some(7));
+    assert!(matches!(
+        host.authenticate_sync("test", "carol", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+}
+
+#[test]
+fn min_failure_duration() {
+    let _serial = SERIAL.lock().unwrap();
+    let min = Duration::from_millis(300);
+    let auth = AuthenticatorBuilder::new()
+        .min_failure_duration(min)
+        .build_with(Simple)
+        .unwrap();
+    let started = Instant::now();
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(started.elapsed() >= min);
+    let started = Instant::now();
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(started.elapsed() < Duration::from_millis(100));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn mock() {
+    let min = Duration::from_millis(200);
+    let auth = MockBackend::new()
+        .user("bob", MockUser::password("xxx"))
+        .build(
+            AuthenticatorBuilder::new()
+                .lockout(
+                    LockoutConfig::new()
+                        .threshold(2)
+                        .lockout(Duration::from_secs(10)),
+                )
+                .min_failure_duration(min),
+        )
+        .unwrap();
+    // unknown users are counted as well
+    for login in ["bob", "bob", "nobody", "nobody"] {
+        let started = Instant::now();
+        assert!(!auth.authenticate_sync("test", login, "wrong").unwrap());
+        assert!(started.elapsed() >= min);
+    }
+    for login in ["bob", "nobody"] {
+        assert!(matches!(
+            auth.authenticate_sync("test", login, "xxx"),
+            Err(Error::LockedOut(_))
+        ));
+    }
+}
+
+#[test]
+fn direct() {
+    let _serial = SERIAL.lock().unwrap();
+    let min = Duration::from_millis(200);
+    let auth = AuthenticatorBuilder::new()
+        .lockout(
+            LockoutConfig::new()
+                .threshold(1)
+                .lockout(Duration::from_secs(10)),
+        )
+        .min_failure_duration(min)
+        .build_with(Direct)
+        .unwrap();
+    let started = Instant::now();
+    let c = auth.chat_sync("test", "bob").unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::NoEcho("Password: ".to_owned())
+    );
+    // the conversation is not relayed
+    assert!(!has_thread("PAMoutcome"));
+    c.tx().send_blocking("wrong".to_owned()).unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::AuthenticationFailed
+    );
+    assert!(started.elapsed() >= min);
+    assert_eq!(c.failure_code(), Some(7));
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+}
@@ -0,0 +1,85 @@
+// Credential cache tests, run with the libpam stub
+#![cfg(all(feature = "cache", not(feature = "link")))]
+use std::time::Duration;
+
+use easypam::{Authenticator, AuthenticatorBuilder, Message};
+
+use stub::{rewrite, setup};
+
+mod stub;
+
+const SCENARIO: &str = "auth [noecho=Password: ] expect=xxx\n";
+// the directory is down, every authentication fails
+const FAILING: &str = "auth rc=7\n";
+
+fn cached(name: &str, ttl: Duration) -> Authenticator {
+    setup(
+        name,
+        &[("test", SCENARIO), ("other", SCENARIO)],
+        AuthenticatorBuilder::new().credential_cache(ttl),
+    )
+}
+
+#[test]
+fn hit() {
+    let auth = cached("cache-hit", Duration::from_secs(10));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    rewrite("cache-hit", "test", FAILING);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn miss() {
+    let auth = cached("cache-miss", Duration::from_secs(10));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    rewrite("cache-miss", "test", FAILING);
+    rewrite("cache-miss", "other", FAILING);
+    // wrong passwords, other logins and services are verified by the stack
+    assert!(!auth.authenticate_sync("test", "bob", "yyy").unwrap());
+    assert!(!auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("other", "bob", "xxx").unwrap());
+    // and the cached entry is kept
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    // conversations are never cached
+    let c = auth.chat_sync("test", "bob").unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::AuthenticationFailed
+    );
+}
+
+#[test]
+fn expiry() {
+    let auth = cached("cache-expiry", Duration::from_millis(200));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    rewrite("cache-expiry", "test", FAILING);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    std::thread::sleep(Duration::from_millis(250));
+    assert!(!auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn invalidate() {
+    let auth = cached("cache-invalidate", Duration::from_secs(10));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    assert!(auth.authenticate_sync("other", "bob", "xxx").unwrap());
+    rewrite("cache-invalidate", "test", FAILING);
+    rewrite("cache-invalidate", "other", FAILING);
+    auth.invalidate_credentials("test", "bob");
+    assert!(!auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    auth.clear_credentials();
+    assert!(!auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("other", "bob", "xxx").unwrap());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn hit_async() {
+    let auth = cached("cache-hit_async", Duration::from_secs(10));
+    assert!(auth.authenticate("test", "bob", "xxx").await.unwrap());
+    rewrite("cache-hit_async", "test", FAILING);
+    assert!(auth.authenticate("test", "bob", "xxx").await.unwrap());
+    assert!(!auth.authenticate("test", "bob", "yyy").await.unwrap());
+}
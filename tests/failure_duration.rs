@@ -0,0 +1,131 @@
+// Minimum failure duration tests, run with the libpam stub
+#![cfg(not(feature = "link"))]
+use std::time::{Duration, Instant};
+
+use easypam::{Authenticator, AuthenticatorBuilder, Error};
+
+use stub::setup;
+
+mod stub;
+
+const MIN: Duration = Duration::from_millis(300);
+
+fn auth(name: &str, builder: AuthenticatorBuilder) -> Authenticator {
+    setup(
+        name,
+        &[
+            ("test", "auth [noecho=Password: ] expect=xxx\n"),
+            ("unknown", "auth rc=10\n"),
+            ("account", "auth\naccount rc=13\n"),
+            (
+                "slow",
+                "auth [noecho=Password: ] expect=xxx fail_delay=500000\n",
+            ),
+            ("stuck", "auth delay=2000\n"),
+            ("stuck_start", "start delay=2000\n"),
+        ],
+        builder.min_failure_duration(MIN),
+    )
+}
+
+fn timed(auth: &Authenticator, service: &str, password: &str) -> (bool, Duration) {
+    let started = Instant::now();
+    let res = auth.authenticate_sync(service, "bob", password).unwrap();
+    (res, started.elapsed())
+}
+
+#[test]
+fn failures() {
+    let auth = auth("failure_duration-failures", AuthenticatorBuilder::new());
+    // unknown users, wrong passwords and account failures take the same time
+    for service in ["test", "unknown", "account"] {
+        let (res, elapsed) = timed(&auth, service, "wrong");
+        assert!(!res, "{}", service);
+        assert!(elapsed >= MIN, "{}: {:?}", service, elapsed);
+        assert!(
+            elapsed < MIN + Duration::from_millis(200),
+            "{}: {:?}",
+            service,
+            elapsed
+        );
+    }
+    // a longer fail delay requested by a module is kept
+    let (res, elapsed) = timed(&auth, "slow", "wrong");
+    assert!(!res);
+    assert!(elapsed >= Duration::from_millis(500));
+}
+
+#[test]
+fn success() {
+    let auth = auth("failure_duration-success", AuthenticatorBuilder::new());
+    let (res, elapsed) = timed(&auth, "test", "xxx");
+    assert!(res);
+    assert!(elapsed < Duration::from_millis(100));
+}
+
+#[test]
+fn jitter() {
+    let auth = auth(
+        "failure_duration-jitter",
+        AuthenticatorBuilder::new().failure_jitter(Duration::from_millis(200)),
+    );
+    for _ in 0..3 {
+        let (res, elapsed) = timed(&auth, "test", "wrong");
+        assert!(!res);
+        assert!(elapsed >= MIN);
+        assert!(elapsed < MIN + Duration::from_millis(400), "{:?}", elapsed);
+    }
+}
+
+#[test]
+fn worker_not_blocked() {
+    let auth = auth(
+        "failure_duration-worker_not_blocked",
+        AuthenticatorBuilder::new().workers(1),
+    );
+    let started = Instant::now();
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let auth = auth.clone();
+            std::thread::spawn(move || auth.authenticate_sync("test", "bob", "wrong").unwrap())
+        })
+        .collect();
+    for handle in handles {
+        assert!(!handle.join().unwrap());
+    }
+    // the delayed outcomes do not occupy the worker
+    assert!(started.elapsed() < MIN * 2);
+}
+
+#[test]
+fn precise() {
+    let auth = auth("failure_duration-precise", AuthenticatorBuilder::new());
+    // the outcomes are not aligned to the supervisor checks
+    for _ in 0..3 {
+        std::thread::sleep(Duration::from_millis(50));
+        let (res, elapsed) = timed(&auth, "test", "wrong");
+        assert!(!res);
+        assert!(elapsed >= MIN);
+        assert!(elapsed < MIN + Duration::from_millis(30), "{:?}", elapsed);
+    }
+}
+
+#[test]
+fn watchdog() {
+    let auth = auth(
+        "failure_duration-watchdog",
+        AuthenticatorBuilder::new()
+            .workers(2)
+            .call_timeout(Duration::from_millis(100)),
+    );
+    let (res, elapsed) = timed(&auth, "stuck", "");
+    assert!(!res);
+    assert!(elapsed >= MIN, "{:?}", elapsed);
+    // errors are delayed as well
+    let started = Instant::now();
+    assert!(matches!(
+        auth.chat_sync("stuck_start", "bob"),
+        Err(Error::Failed(_))
+    ));
+    assert!(started.elapsed() >= MIN, "{:?}", started.elapsed());
+}
@@ -0,0 +1,91 @@
+// Concurrency limit tests, run with the libpam stub
+#![cfg(not(feature = "link"))]
+use std::time::Duration;
+
+use easypam::{AuthenticatorBuilder, Error, Message};
+
+use stub::setup;
+
+mod stub;
+
+const SCENARIO: &str = "auth [noecho=Password: ] expect=xxx\n";
+
+#[test]
+fn per_user() {
+    let auth = setup(
+        "limits-per_user",
+        &[("test", SCENARIO)],
+        AuthenticatorBuilder::new().workers(4).max_per_user(1),
+    );
+    let c = auth.chat_sync("test", "bob").unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::NoEcho("Password: ".to_owned())
+    );
+    assert!(matches!(
+        auth.chat_sync("test", "bob"),
+        Err(Error::TooManyForUser(login)) if login == "bob"
+    ));
+    // other users and hosts are not limited
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    // the slot is released when the conversation is finished
+    c.tx().send_blocking("xxx".to_owned()).unwrap();
+    assert_eq!(c.rx().recv_blocking().unwrap(), Message::Authenticated);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn per_host() {
+    let auth = setup(
+        "limits-per_host",
+        &[("test", SCENARIO)],
+        AuthenticatorBuilder::new().workers(4).max_per_host(2),
+    );
+    let host = auth.with_remote_host("10.0.0.1");
+    let c1 = host.chat_sync("test", "bob").unwrap();
+    let c2 = host.chat_sync("test", "alice").unwrap();
+    assert!(matches!(
+        host.chat_sync("test", "carol"),
+        Err(Error::TooManyForHost(host)) if host == "10.0.0.1"
+    ));
+    assert!(
+        auth.with_remote_host("10.0.0.2")
+            .authenticate_sync("test", "carol", "xxx")
+            .unwrap()
+    );
+    // requests without a remote host are not limited
+    assert!(auth.authenticate_sync("test", "carol", "xxx").unwrap());
+    // an abandoned conversation releases the slot as well, when the worker notices it
+    drop(c1);
+    drop(c2);
+    let mut res = host.chat_sync("test", "carol");
+    for _ in 0..20 {
+        if !matches!(res, Err(Error::TooManyForHost(_))) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        res = host.chat_sync("test", "carol");
+    }
+    assert!(res.is_ok());
+}
+
+#[test]
+fn abandoned_worker() {
+    let auth = setup(
+        "limits-abandoned_worker",
+        &[("stuck", "auth delay=2000\n"), ("test", SCENARIO)],
+        AuthenticatorBuilder::new()
+            .workers(2)
+            .max_per_user(1)
+            .call_timeout(Duration::from_millis(200)),
+    );
+    let c = auth.chat_sync("stuck", "bob").unwrap();
+    assert_eq!(
+        c.rx()
+            .recv_blocking_timeout(Duration::from_secs(1))
+            .unwrap(),
+        Message::AuthenticationFailed
+    );
+    // the slot is released by the watchdog, not by the stuck worker
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
@@ -0,0 +1,190 @@
+// Brute-force protection tests, run with the libpam stub
+#![cfg(not(feature = "link"))]
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use easypam::{Authenticator, AuthenticatorBuilder, Error, LockoutConfig};
+
+use stub::setup;
+
+mod stub;
+
+const SCENARIO: &str = "auth [noecho=Password: ] expect=xxx\n";
+
+fn lockout(name: &str, config: LockoutConfig) -> Authenticator {
+    setup(
+        name,
+        &[("test", SCENARIO)],
+        AuthenticatorBuilder::new().workers(2).lockout(config),
+    )
+}
+
+fn locked_for(auth: &Authenticator, login: &str) -> Option<Duration> {
+    match auth.authenticate_sync("test", login, "wrong") {
+        Ok(_) => None,
+        Err(Error::LockedOut(d)) => Some(d),
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+fn state_file(name: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("lockout-{}.state", name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn threshold() {
+    let auth = lockout(
+        "lockout-threshold",
+        LockoutConfig::new()
+            .threshold(2)
+            .lockout(Duration::from_millis(300)),
+    );
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    // the correct password is rejected as well while locked
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", "xxx"),
+        Err(Error::LockedOut(d)) if d <= Duration::from_millis(300)
+    ));
+    // other logins are not affected
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    std::thread::sleep(Duration::from_millis(350));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn backoff() {
+    let auth = lockout(
+        "lockout-backoff",
+        LockoutConfig::new()
+            .threshold(1)
+            .lockout(Duration::from_millis(200))
+            .max_lockout(Duration::from_millis(600)),
+    );
+    assert_eq!(locked_for(&auth, "bob"), None);
+    assert!(locked_for(&auth, "bob").unwrap() <= Duration::from_millis(200));
+    std::thread::sleep(Duration::from_millis(250));
+    // the lockout period is doubled on every next failure, up to the maximum
+    assert_eq!(locked_for(&auth, "bob"), None);
+    assert!(locked_for(&auth, "bob").unwrap() > Duration::from_millis(200));
+    std::thread::sleep(Duration::from_millis(450));
+    assert_eq!(locked_for(&auth, "bob"), None);
+    let d = locked_for(&auth, "bob").unwrap();
+    assert!(d > Duration::from_millis(400) && d <= Duration::from_millis(600));
+}
+
+#[test]
+fn success() {
+    let auth = lockout(
+        "lockout-success",
+        LockoutConfig::new()
+            .threshold(2)
+            .lockout(Duration::from_secs(10)),
+    );
+    // a successful login resets the login counter
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "wrong").unwrap());
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    // but not the host one, otherwise a known account would allow to guess the others
+    let host = auth.with_remote_host("10.0.0.1");
+    assert!(!host.authenticate_sync("test", "alice", "wrong").unwrap());
+    assert!(host.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(!host.authenticate_sync("test", "carol", "wrong").unwrap());
+    assert!(matches!(
+        host.authenticate_sync("test", "dave", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(auth.authenticate_sync("test", "dave", "xxx").unwrap());
+}
+
+#[test]
+fn max_entries() {
+    let auth = lockout(
+        "lockout-max_entries",
+        LockoutConfig::new()
+            .threshold(1)
+            .lockout(Duration::from_secs(10))
+            .per_host(false)
+            .max_entries(10),
+    );
+    for i in 0..30 {
+        assert_eq!(locked_for(&auth, &format!("user{}", i)), None);
+        // the entries are ordered by time, in milliseconds
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    // the oldest entries are removed
+    assert_eq!(locked_for(&auth, "user0"), None);
+    assert!(locked_for(&auth, "user29").is_some());
+}
+
+#[test]
+fn state_file_round_trip() {
+    let path = state_file("round_trip");
+    let config = LockoutConfig::new()
+        .threshold(1)
+        .lockout(Duration::from_secs(10))
+        .state_file(&path);
+    let auth = lockout("lockout-state_file_round_trip", config.clone());
+    assert!(
+        !auth
+            .with_remote_host("10.0.0.1")
+            .authenticate_sync("test", "bob", "wrong")
+            .unwrap()
+    );
+    // the state is saved in background
+    let started = Instant::now();
+    while std::fs::read_to_string(&path).map_or(true, |data| data.lines().count() < 2) {
+        assert!(started.elapsed() < Duration::from_secs(3));
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    drop(auth);
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    let auth = lockout("lockout-state_file_round_trip", config);
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(matches!(
+        auth.with_remote_host("10.0.0.1")
+            .authenticate_sync("test", "alice", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+}
+
+#[test]
+fn state_file_corrupted() {
+    let path = state_file("corrupted");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    std::fs::write(
+        &path,
+        format!(
+            "garbage\nx 1 {now} {locked} alice\nu one {now} {locked} bob\nu 1 {now} {locked} eve\n",
+            locked = now + 10_000
+        ),
+    )
+    .unwrap();
+    let auth = lockout(
+        "lockout-state_file_corrupted",
+        LockoutConfig::new().state_file(&path),
+    );
+    // the valid lines are loaded, the invalid ones are ignored
+    assert!(matches!(
+        auth.authenticate_sync("test", "eve", "xxx"),
+        Err(Error::LockedOut(_))
+    ));
+    assert!(auth.authenticate_sync("test", "alice", "xxx").unwrap());
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use easypam::testing::{MockBackend, MockUser};
+use easypam::{AuthenticatorBuilder, Error, Message};
+
+fn main() {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("trace"));
+    let backend = MockBackend::new()
+        .user("test", MockUser::password("xxx"))
+        .user(
+            "expired",
+            MockUser::new()
+                .info("Welcome")
+                .no_echo("Password: ", "xxx")
+                .delay(Duration::from_millis(100))
+                .error("Your account has expired")
+                .validation_failed(),
+        )
+        .service_user("sshd", "test", MockUser::password("yyy"));
+    let authenticator = backend.authenticator();
+    assert!(authenticator.authenticate_sync("system-auth", "test", "xxx").unwrap());
+    assert!(!authenticator.authenticate_sync("system-auth", "test", "xx").unwrap());
+    assert!(!authenticator.authenticate_sync("sshd", "test", "xxx").unwrap());
+    assert!(authenticator.authenticate_sync("sshd", "test", "yyy").unwrap());
+    assert!(!authenticator.authenticate_sync("system-auth", "nobody", "xxx").unwrap());
+    let conversation = authenticator
+        .chat_sync("system-auth", "expired")
+        .expect("failed to create conversation");
+    let mut messages = Vec::new();
+    while let Ok(msg) = conversation.rx().recv_blocking() {
+        if let Message::NoEcho(_) = msg {
+            conversation
+                .tx()
+                .send_blocking("xxx".to_string())
+                .expect("failed to send password");
+        }
+        messages.push(msg.clone());
+        if msg == Message::ValidationFailed {
+            break;
+        }
+    }
+    assert_eq!(
+        messages,
+        [
+            Message::Info("Welcome".to_owned()),
+            Message::NoEcho("Password: ".to_owned()),
+            Message::Error("Your account has expired".to_owned()),
+            Message::ValidationFailed
+        ]
+    );
+    assert_eq!(conversation.failure_code(), Some(13));
+    let authenticator = MockBackend::new()
+        .user("test", MockUser::password("xxx"))
+        .build(AuthenticatorBuilder::new().max_per_user(1))
+        .unwrap();
+    let _conversation = authenticator.chat_sync("system-auth", "test").unwrap();
+    assert!(matches!(
+        authenticator.chat_sync("system-auth", "test"),
+        Err(Error::TooManyForUser(_))
+    ));
+    println!("Mock tests passed");
+}
@@ -0,0 +1,251 @@
+//! A PAM module for easypam integration tests, the behaviour is controlled by the module arguments
+//! (the same for all the management groups):
+//!
+//! * `echo=TEXT`, `noecho=TEXT`, `info=TEXT`, `error=TEXT`, `style=N:TEXT` - messages, sent to the
+//!   application one by one
+//! * `batch` - all the messages are sent in a single conversation call
+//! * `expect=TEXT` - the expected prompt answers (in order), the module fails with `PAM_AUTH_ERR`
+//!   if an answer does not match
+//! * `delay=MS` - sleeps before the conversation
+//! * `fail_delay=USEC` - calls `pam_fail_delay`
+//! * `user=NAME` - sets `PAM_USER`
+//! * `env=NAME=VALUE` - sets a PAM environment variable
+//! * `expect_user=NAME`, `expect_rhost=HOST` - checks the items, fails with `PAM_AUTH_ERR`
+//! * `rc=N` - the return code (default: `PAM_SUCCESS`)
+//!
+//! Conversation errors are returned as `PAM_CONV_ERR`. Arguments with spaces must be put into
+//! brackets, e.g. `[noecho=Password: ]`.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
+use std::time::Duration;
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_CONV_ERR: c_int = 19;
+
+const PAM_USER: c_int = 2;
+const PAM_CONV: c_int = 5;
+const PAM_RHOST: c_int = 4;
+
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+type ConvFn =
+    extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<ConvFn>,
+    appdata_ptr: *mut c_void,
+}
+
+unsafe extern "C" {
+    fn pam_get_item(pamh: *const c_void, item_type: c_int, item: *mut *const c_void) -> c_int;
+    fn pam_set_item(pamh: *mut c_void, item_type: c_int, item: *const c_void) -> c_int;
+    fn pam_fail_delay(pamh: *mut c_void, usec: c_uint) -> c_int;
+    fn pam_putenv(pamh: *mut c_void, name_value: *const c_char) -> c_int;
+    fn free(ptr: *mut c_void);
+}
+
+#[derive(Default)]
+struct Args {
+    messages: Vec<(c_int, CString)>,
+    batch: bool,
+    expect: Vec<String>,
+    delay: Option<Duration>,
+    fail_delay: Option<c_uint>,
+    user: Option<CString>,
+    env: Vec<CString>,
+    expect_user: Option<String>,
+    expect_rhost: Option<String>,
+    rc: c_int,
+}
+
+impl Args {
+    unsafe fn parse(argc: c_int, argv: *const *const c_char) -> Self {
+        let mut args = Args::default();
+        for i in 0..usize::try_from(argc).unwrap_or_default() {
+            let arg = unsafe { CStr::from_ptr(*argv.add(i)) }.to_string_lossy();
+            let (name, value) = arg.split_once('=').unwrap_or((&arg, ""));
+            let text = || CString::new(value).unwrap_or_default();
+            match name {
+                "echo" => args.messages.push((PAM_PROMPT_ECHO_ON, text())),
+                "noecho" => args.messages.push((PAM_PROMPT_ECHO_OFF, text())),
+                "info" => args.messages.push((PAM_TEXT_INFO, text())),
+                "error" => args.messages.push((PAM_ERROR_MSG, text())),
+                "style" => {
+                    let (style, text) = value.split_once(':').unwrap_or((value, ""));
+                    args.messages.push((
+                        style.parse().unwrap_or_default(),
+                        CString::new(text).unwrap_or_default(),
+                    ));
+                }
+                "batch" => args.batch = true,
+                "expect" => args.expect.push(value.to_owned()),
+                "delay" => args.delay = value.parse().ok().map(Duration::from_millis),
+                "fail_delay" => args.fail_delay = value.parse().ok(),
+                "user" => args.user = Some(text()),
+                "env" => args.env.push(text()),
+                "expect_user" => args.expect_user = Some(value.to_owned()),
+                "expect_rhost" => args.expect_rhost = Some(value.to_owned()),
+                "rc" => args.rc = value.parse().unwrap_or(PAM_AUTH_ERR),
+                _ => {}
+            }
+        }
+        args
+    }
+}
+
+unsafe fn get_str_item(pamh: *mut c_void, item_type: c_int) -> Option<String> {
+    let mut item: *const c_void = std::ptr::null();
+    if unsafe { pam_get_item(pamh, item_type, &mut item) } != PAM_SUCCESS || item.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(item.cast()) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+// sends the messages in a single call, returns the answers to the prompts
+unsafe fn converse(pamh: *mut c_void, messages: &[(c_int, CString)]) -> Result<Vec<String>, c_int> {
+    let mut item: *const c_void = std::ptr::null();
+    if unsafe { pam_get_item(pamh, PAM_CONV, &mut item) } != PAM_SUCCESS || item.is_null() {
+        return Err(PAM_CONV_ERR);
+    }
+    let conv = unsafe { &*item.cast::<PamConv>() };
+    let Some(conv_fn) = conv.conv else {
+        return Err(PAM_CONV_ERR);
+    };
+    let msgs: Vec<PamMessage> = messages
+        .iter()
+        .map(|(style, text)| PamMessage {
+            msg_style: *style,
+            msg: text.as_ptr(),
+        })
+        .collect();
+    let mut msg_ptrs: Vec<*const PamMessage> = msgs.iter().map(|m| m as *const _).collect();
+    let mut resp: *mut PamResponse = std::ptr::null_mut();
+    let rc = conv_fn(
+        c_int::try_from(msg_ptrs.len()).unwrap_or_default(),
+        msg_ptrs.as_mut_ptr(),
+        &mut resp,
+        conv.appdata_ptr,
+    );
+    if rc != PAM_SUCCESS {
+        return Err(PAM_CONV_ERR);
+    }
+    let mut answers = Vec::new();
+    if resp.is_null() {
+        return Ok(answers);
+    }
+    for (i, (style, _)) in messages.iter().enumerate() {
+        let r = unsafe { &*resp.add(i) };
+        if !r.resp.is_null() {
+            if matches!(*style, PAM_PROMPT_ECHO_ON | PAM_PROMPT_ECHO_OFF) {
+                answers.push(
+                    unsafe { CStr::from_ptr(r.resp) }
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            }
+            unsafe { free(r.resp.cast()) };
+        }
+    }
+    unsafe { free(resp.cast()) };
+    Ok(answers)
+}
+
+unsafe fn run(pamh: *mut c_void, argc: c_int, argv: *const *const c_char) -> c_int {
+    let args = unsafe { Args::parse(argc, argv) };
+    if let Some(delay) = args.delay {
+        std::thread::sleep(delay);
+    }
+    if let Some(usec) = args.fail_delay {
+        unsafe { pam_fail_delay(pamh, usec) };
+    }
+    if let Some(ref user) = args.user
+        && unsafe { pam_set_item(pamh, PAM_USER, user.as_ptr().cast()) } != PAM_SUCCESS
+    {
+        return PAM_AUTH_ERR;
+    }
+    for name_value in &args.env {
+        if unsafe { pam_putenv(pamh, name_value.as_ptr()) } != PAM_SUCCESS {
+            return PAM_AUTH_ERR;
+        }
+    }
+    if let Some(ref expected) = args.expect_user
+        && unsafe { get_str_item(pamh, PAM_USER) }.as_ref() != Some(expected)
+    {
+        return PAM_AUTH_ERR;
+    }
+    if let Some(ref expected) = args.expect_rhost
+        && unsafe { get_str_item(pamh, PAM_RHOST) }.as_ref() != Some(expected)
+    {
+        return PAM_AUTH_ERR;
+    }
+    let mut answers = Vec::new();
+    if args.batch {
+        match unsafe { converse(pamh, &args.messages) } {
+            Ok(a) => answers = a,
+            Err(rc) => return rc,
+        }
+    } else {
+        for message in args.messages.chunks(1) {
+            match unsafe { converse(pamh, message) } {
+                Ok(a) => answers.extend(a),
+                Err(rc) => return rc,
+            }
+        }
+    }
+    if !args.expect.is_empty() && answers != args.expect {
+        return PAM_AUTH_ERR;
+    }
+    args.rc
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_sm_authenticate(
+    pamh: *mut c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    unsafe { run(pamh, argc, argv) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_sm_acct_mgmt(
+    pamh: *mut c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    unsafe { run(pamh, argc, argv) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_sm_setcred(
+    _pamh: *mut c_void,
+    _flags: c_int,
+    _argc: c_int,
+    _argv: *const *const c_char,
+) -> c_int {
+    PAM_SUCCESS
+}
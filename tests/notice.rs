@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use easypam::{Message, Notice};
+
+const DAY: Duration = Duration::from_secs(86400);
+
+fn check(notices: &[(&str, Option<Notice>)]) {
+    for (text, notice) in notices {
+        assert_eq!(&Notice::parse(text), notice, "{}", text);
+    }
+}
+
+#[test]
+fn password_expiry() {
+    check(&[
+        // pam_unix
+        (
+            "Warning: your password will expire in 3 days.",
+            Some(Notice::PasswordExpiresIn(DAY * 3)),
+        ),
+        (
+            "Warning: your password will expire in 1 day.",
+            Some(Notice::PasswordExpiresIn(DAY)),
+        ),
+        (
+            "You are required to change your password immediately (password expired)",
+            Some(Notice::PasswordExpired),
+        ),
+        (
+            "You are required to change your password immediately (administrator enforced)",
+            Some(Notice::PasswordExpired),
+        ),
+        // pam_sss
+        (
+            "Your password will expire in 5 hour(s).",
+            Some(Notice::PasswordExpiresIn(Duration::from_secs(5 * 3600))),
+        ),
+        (
+            "Password expired. Change your password now.",
+            Some(Notice::PasswordExpired),
+        ),
+    ]);
+}
+
+#[test]
+fn password_rejected() {
+    check(&[
+        // pam_unix
+        (
+            "You must choose a longer password.",
+            Some(Notice::PasswordRejected(
+                "You must choose a longer password.".to_owned(),
+            )),
+        ),
+        (
+            "Sorry, passwords do not match.",
+            Some(Notice::PasswordMismatch),
+        ),
+        // pam_pwquality, cracklib
+        (
+            "BAD PASSWORD: The password is shorter than 8 characters",
+            Some(Notice::PasswordRejected(
+                "The password is shorter than 8 characters".to_owned(),
+            )),
+        ),
+        (
+            "BAD PASSWORD: it is based on a dictionary word",
+            Some(Notice::PasswordRejected(
+                "it is based on a dictionary word".to_owned(),
+            )),
+        ),
+    ]);
+}
+
+#[test]
+fn account() {
+    check(&[
+        // pam_unix
+        (
+            "Your account has expired; please contact your system administrator.",
+            Some(Notice::AccountExpired),
+        ),
+        // pam_faillock, pam_tally2
+        (
+            "The account is locked due to 3 failed logins.",
+            Some(Notice::AccountLocked {
+                failed_logins: Some(3),
+                unlock_in: None,
+            }),
+        ),
+        (
+            "(10 minutes left to unlock)",
+            Some(Notice::AccountLocked {
+                failed_logins: None,
+                unlock_in: Some(Duration::from_secs(600)),
+            }),
+        ),
+        (
+            "Account temporarily locked (25 seconds left)",
+            Some(Notice::AccountLocked {
+                failed_logins: None,
+                unlock_in: Some(Duration::from_secs(25)),
+            }),
+        ),
+        (
+            "There were 4 failed login attempts since the last successful login.",
+            Some(Notice::FailedAttempts(4)),
+        ),
+        (
+            "There was 1 failed login attempt since the last successful login.",
+            Some(Notice::FailedAttempts(1)),
+        ),
+    ]);
+}
+
+#[test]
+fn last_login() {
+    check(&[
+        // pam_lastlog
+        (
+            "Last login: Mon Jan  1 12:00:00 UTC 2024 from 10.0.0.1 on pts/0",
+            Some(Notice::LastLogin {
+                time: "Mon Jan  1 12:00:00 UTC 2024".to_owned(),
+                from: Some("10.0.0.1".to_owned()),
+                tty: Some("pts/0".to_owned()),
+            }),
+        ),
+        (
+            "Last login: Mon Jan  1 12:00:00 UTC 2024 on tty1",
+            Some(Notice::LastLogin {
+                time: "Mon Jan  1 12:00:00 UTC 2024".to_owned(),
+                from: None,
+                tty: Some("tty1".to_owned()),
+            }),
+        ),
+        (
+            "Last failed login: Tue Jan  2 08:00:00 UTC 2024 from 10.0.0.2 on ssh:notty",
+            Some(Notice::LastFailedLogin {
+                time: "Tue Jan  2 08:00:00 UTC 2024".to_owned(),
+                from: Some("10.0.0.2".to_owned()),
+                tty: Some("ssh:notty".to_owned()),
+            }),
+        ),
+    ]);
+}
+
+#[test]
+fn unknown() {
+    check(&[("Welcome to the system", None), ("Привет, İstanbul", None)]);
+}
+
+#[test]
+fn messages() {
+    assert_eq!(
+        Message::Error("Sorry, passwords do not match.".to_owned()).notice(),
+        Some(Notice::PasswordMismatch)
+    );
+    assert_eq!(
+        Message::Info("Warning: your password will expire in 2 days".to_owned()).notice(),
+        Some(Notice::PasswordExpiresIn(DAY * 2))
+    );
+    assert_eq!(
+        Message::NoEcho("Password expired".to_owned()).notice(),
+        None
+    );
+}
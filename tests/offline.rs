@@ -0,0 +1,111 @@
+// Offline authentication tests, run with the libpam stub
+#![cfg(all(feature = "offline", not(feature = "link")))]
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use easypam::{Authenticator, AuthenticatorBuilder, OfflineConfig};
+
+use stub::{rewrite, setup};
+
+mod stub;
+
+const SCENARIO: &str = "auth [noecho=Password: ] expect=xxx\n";
+// PAM_AUTHINFO_UNAVAIL, the directory is unreachable
+const UNAVAILABLE: &str = "auth rc=9\n";
+
+fn store_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.verifiers", name))
+}
+
+fn offline(name: &str, max_age: Duration, scenario: &str) -> Authenticator {
+    setup(
+        name,
+        &[("test", scenario), ("other", scenario), ("plain", scenario)],
+        AuthenticatorBuilder::new().offline(
+            OfflineConfig::new(store_path(name))
+                .service("test", max_age)
+                .service("other", max_age),
+        ),
+    )
+}
+
+fn fresh(name: &str, max_age: Duration) -> Authenticator {
+    let _ = std::fs::remove_file(store_path(name));
+    offline(name, max_age, SCENARIO)
+}
+
+fn rewrite_all(name: &str, scenario: &str) {
+    for service in ["test", "other", "plain"] {
+        rewrite(name, service, scenario);
+    }
+}
+
+#[test]
+fn fallback() {
+    let auth = fresh("offline-fallback", Duration::from_secs(60));
+    // nothing is stored yet
+    rewrite_all("offline-fallback", UNAVAILABLE);
+    assert!(!auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    rewrite_all("offline-fallback", SCENARIO);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(
+        std::fs::read_to_string(store_path("offline-fallback"))
+            .unwrap()
+            .contains(" test bob\n")
+    );
+    rewrite_all("offline-fallback", UNAVAILABLE);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "yyy").unwrap());
+    // the verifiers are kept per service
+    assert!(!auth.authenticate_sync("other", "bob", "xxx").unwrap());
+    // other failures do not fall back
+    rewrite_all("offline-fallback", "auth rc=7\n");
+    assert!(!auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn max_age() {
+    let auth = fresh("offline-max_age", Duration::from_millis(300));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    rewrite_all("offline-max_age", UNAVAILABLE);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    std::thread::sleep(Duration::from_millis(350));
+    assert!(!auth.authenticate_sync("test", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn services() {
+    let auth = fresh("offline-services", Duration::from_secs(60));
+    // the service has no fallback, the verifier is not stored
+    assert!(auth.authenticate_sync("plain", "bob", "xxx").unwrap());
+    assert!(!store_path("offline-services").exists());
+    rewrite_all("offline-services", UNAVAILABLE);
+    assert!(!auth.authenticate_sync("plain", "bob", "xxx").unwrap());
+}
+
+#[test]
+fn reload() {
+    let auth = fresh("offline-reload", Duration::from_secs(60));
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    drop(auth);
+    let path = store_path("offline-reload");
+    let data = std::fs::read_to_string(&path).unwrap();
+    // invalid lines are skipped on load
+    std::fs::write(&path, format!("garbage\n1 zz zz test alice\n{}", data)).unwrap();
+    let auth = offline("offline-reload", Duration::from_secs(60), UNAVAILABLE);
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "yyy").unwrap());
+    assert!(!auth.authenticate_sync("test", "alice", "xxx").unwrap());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn fallback_async() {
+    let auth = fresh("offline-fallback_async", Duration::from_secs(60));
+    assert!(auth.authenticate("test", "bob", "xxx").await.unwrap());
+    rewrite_all("offline-fallback_async", UNAVAILABLE);
+    assert!(auth.authenticate("test", "bob", "xxx").await.unwrap());
+    assert!(!auth.authenticate("test", "bob", "yyy").await.unwrap());
+}
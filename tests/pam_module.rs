@@ -0,0 +1,326 @@
+// Runs the real libpam path with the bundled test module (tests/module) and a private PAM
+// configuration directory, no root or system users are required. The tests are skipped if libpam
+// is not available or does not support pam_start_confdir (libpam < 1.4).
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use easypam::{Authenticator, AuthenticatorBuilder, Message};
+
+const PAM_AUTH_ERR: i32 = 7;
+const PAM_CONV_ERR: i32 = 19;
+const PAM_ACCT_EXPIRED: i32 = 13;
+
+fn module() -> &'static Path {
+    static MODULE: OnceLock<PathBuf> = OnceLock::new();
+    MODULE.get_or_init(|| {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libpam_easypam_test.so");
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/module/pam_easypam_test.rs");
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+        let output = Command::new(rustc)
+            .args([
+                "--edition",
+                "2024",
+                "--crate-type",
+                "cdylib",
+                "-C",
+                "opt-level=1",
+            ])
+            .args(["-C", "link-arg=-l:libpam.so.0", "-o"])
+            .arg(&out)
+            .arg(&src)
+            .output()
+            .expect("unable to run rustc");
+        assert!(
+            output.status.success(),
+            "unable to build the test module: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        out
+    })
+}
+
+// services: name, config lines with MODULE placeholder
+fn setup(
+    name: &str,
+    services: &[(&str, &str)],
+    builder: AuthenticatorBuilder,
+) -> Option<Authenticator> {
+    match AuthenticatorBuilder::new().probe() {
+        Ok(caps) if caps.pam_start_confdir => {}
+        Ok(_) => {
+            eprintln!("pam_start_confdir is not supported by libpam, skipping");
+            return None;
+        }
+        Err(e) => {
+            eprintln!("libpam is not available ({}), skipping", e);
+            return None;
+        }
+    }
+    let module = module();
+    let confdir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("pam.d-{}", name));
+    std::fs::create_dir_all(&confdir).unwrap();
+    for (service, config) in services {
+        let config = config.replace("MODULE", &module.to_string_lossy());
+        std::fs::write(confdir.join(service), config).unwrap();
+    }
+    Some(builder.confdir(confdir).build().unwrap())
+}
+
+// answers the prompts in order, returns all the messages and the failure code
+fn converse(
+    auth: &Authenticator,
+    service: &str,
+    login: &str,
+    answers: &[&str],
+) -> (Vec<Message>, Option<i32>) {
+    let c = auth.chat_sync(service, login).unwrap();
+    let mut answers = answers.iter();
+    let mut messages = Vec::new();
+    while let Ok(msg) = c.rx().recv_blocking() {
+        messages.push(msg.clone());
+        match msg {
+            Message::Echo(_) | Message::NoEcho(_) => {
+                c.tx()
+                    .send_blocking((*answers.next().unwrap()).to_owned())
+                    .unwrap();
+            }
+            Message::Info(_) | Message::Error(_) => {}
+            Message::Authenticated | Message::AuthenticationFailed | Message::ValidationFailed => {
+                break;
+            }
+        }
+    }
+    (messages, c.failure_code())
+}
+
+#[test]
+fn password() {
+    let Some(auth) = setup(
+        "password",
+        &[(
+            "test",
+            "auth required MODULE [noecho=Password: ] expect=xxx\naccount required MODULE\n",
+        )],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    assert!(!auth.authenticate_sync("test", "bob", "xx").unwrap());
+    let (messages, code) = converse(&auth, "test", "bob", &["xx"]);
+    assert_eq!(
+        messages,
+        [
+            Message::NoEcho("Password: ".to_owned()),
+            Message::AuthenticationFailed
+        ]
+    );
+    assert_eq!(code, Some(PAM_AUTH_ERR));
+}
+
+#[test]
+fn message_styles() {
+    let Some(auth) = setup(
+        "message_styles",
+        &[(
+            "test",
+            "auth required MODULE [info=Hello] [echo=Login: ] [error=Bad luck] [noecho=Password: ] \
+                expect=bob expect=xxx\n\
+             account required MODULE\n",
+        )],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    let (messages, _) = converse(&auth, "test", "bob", &["bob", "xxx"]);
+    assert_eq!(
+        messages,
+        [
+            Message::Info("Hello".to_owned()),
+            Message::Echo("Login: ".to_owned()),
+            Message::Error("Bad luck".to_owned()),
+            Message::NoEcho("Password: ".to_owned()),
+            Message::Authenticated
+        ]
+    );
+}
+
+#[test]
+fn mixed_batch() {
+    let Some(auth) = setup(
+        "mixed_batch",
+        &[(
+            "test",
+            "auth required MODULE batch [info=Hello] [noecho=Password: ] [error=Bad luck] \
+                [echo=Token: ] expect=xxx expect=123\n\
+             account required MODULE\n",
+        )],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    let (messages, _) = converse(&auth, "test", "bob", &["xxx", "123"]);
+    assert_eq!(
+        messages,
+        [
+            Message::Info("Hello".to_owned()),
+            Message::NoEcho("Password: ".to_owned()),
+            Message::Error("Bad luck".to_owned()),
+            Message::Echo("Token: ".to_owned()),
+            Message::Authenticated
+        ]
+    );
+    let (messages, _) = converse(&auth, "test", "bob", &["xxx", "12"]);
+    assert_eq!(messages.last(), Some(&Message::AuthenticationFailed));
+}
+
+#[test]
+fn unknown_style() {
+    let Some(auth) = setup(
+        "unknown_style",
+        &[(
+            "test",
+            "auth required MODULE batch [info=Hello] [style=5:Radio]\naccount required MODULE\n",
+        )],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    // the whole batch is rejected
+    let (messages, code) = converse(&auth, "test", "bob", &[]);
+    assert_eq!(
+        messages,
+        [
+            Message::Info("Hello".to_owned()),
+            Message::AuthenticationFailed
+        ]
+    );
+    assert_eq!(code, Some(PAM_CONV_ERR));
+}
+
+#[test]
+fn account_failure() {
+    let Some(auth) = setup(
+        "account_failure",
+        &[(
+            "test",
+            "auth required MODULE\naccount required MODULE [error=Account expired] rc=13\n",
+        )],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    let (messages, code) = converse(&auth, "test", "bob", &[]);
+    assert_eq!(
+        messages,
+        [
+            Message::Error("Account expired".to_owned()),
+            Message::ValidationFailed
+        ]
+    );
+    assert_eq!(code, Some(PAM_ACCT_EXPIRED));
+}
+
+#[test]
+fn items() {
+    let Some(auth) = setup(
+        "items",
+        &[
+            (
+                "rhost",
+                "auth required MODULE expect_rhost=10.0.0.1\naccount required MODULE\n",
+            ),
+            (
+                "remap",
+                "auth required MODULE user=alice\nauth required MODULE expect_user=alice\n\
+                 account required MODULE expect_user=alice\n",
+            ),
+        ],
+        AuthenticatorBuilder::new(),
+    ) else {
+        return;
+    };
+    assert!(!auth.authenticate_sync("rhost", "bob", "").unwrap());
+    assert!(
+        auth.with_remote_host("10.0.0.1")
+            .authenticate_sync("rhost", "bob", "")
+            .unwrap()
+    );
+    assert!(auth.authenticate_sync("remap", "bob", "").unwrap());
+}
+
+#[test]
+fn fail_delay() {
+    let Some(auth) = setup(
+        "fail_delay",
+        &[(
+            "test",
+            "auth required MODULE fail_delay=1000000 rc=7\naccount required MODULE\n",
+        )],
+        AuthenticatorBuilder::new().workers(1),
+    ) else {
+        return;
+    };
+    // libpam randomizes the delay by 25%, the delays must not block the single worker
+    let started = Instant::now();
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let auth = auth.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                assert!(!auth.authenticate_sync("test", "bob", "").unwrap());
+                started.elapsed()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap() >= Duration::from_millis(700));
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn stuck_module() {
+    let Some(auth) = setup(
+        "stuck_module",
+        &[
+            (
+                "stuck",
+                "auth required MODULE delay=5000\naccount required MODULE\n",
+            ),
+            ("test", "auth required MODULE\naccount required MODULE\n"),
+        ],
+        AuthenticatorBuilder::new()
+            .workers(1)
+            .call_timeout(Duration::from_millis(300)),
+    ) else {
+        return;
+    };
+    let started = Instant::now();
+    assert!(!auth.authenticate_sync("stuck", "bob", "").unwrap());
+    assert!(started.elapsed() < Duration::from_secs(2));
+    // served by a replacement worker
+    assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+}
+
+#[cfg(feature = "link")]
+#[test]
+fn linked_library() {
+    // libpam is linked at build time, a custom library can not be set
+    assert!(
+        AuthenticatorBuilder::new()
+            .library("/usr/lib/libpam.so.0")
+            .probe()
+            .is_err()
+    );
+    assert!(
+        AuthenticatorBuilder::new()
+            .library_search(["libpam.so.0", "libpam.so"])
+            .build()
+            .is_err()
+    );
+}
@@ -0,0 +1,135 @@
+// Runs easypam with the libpam stub (tests/stub) instead of the system libpam, the tests require
+// neither PAM nor root. The stub can not be used if libpam is linked at build time.
+#![cfg(not(feature = "link"))]
+use std::time::{Duration, Instant};
+
+use easypam::{AuthenticatorBuilder, Message};
+
+use stub::{converse, setup, stub};
+
+mod stub;
+
+const PAM_AUTH_ERR: i32 = 7;
+const PAM_AUTHINFO_UNAVAIL: i32 = 9;
+const PAM_ACCT_EXPIRED: i32 = 13;
+
+#[test]
+fn capabilities() {
+    let caps = AuthenticatorBuilder::new().library(stub()).probe().unwrap();
+    assert_eq!(caps.path.as_deref(), Some(stub()));
+    assert!(caps.pam_start_confdir);
+    assert!(caps.pam_fail_delay);
+    assert!(caps.pam_getenvlist);
+}
+
+#[test]
+fn password() {
+    let auth = setup(
+        "password",
+        &[(
+            "test",
+            "# a typical login\nauth [noecho=Password: ] expect=xxx\n",
+        )],
+        AuthenticatorBuilder::new(),
+    );
+    assert!(auth.authenticate_sync("test", "bob", "xxx").unwrap());
+    let (messages, code) = converse(&auth, "test", "bob", &["xx"]);
+    assert_eq!(
+        messages,
+        [
+            Message::NoEcho("Password: ".to_owned()),
+            Message::AuthenticationFailed
+        ]
+    );
+    assert_eq!(code, Some(PAM_AUTH_ERR));
+}
+
+#[test]
+fn stages() {
+    let auth = setup(
+        "stages",
+        &[
+            (
+                "test",
+                "auth [info=Hello] [echo=Token: ] expect=123\n\
+                 auth env=HOME=/home/bob env=HOME env=SHELL=/bin/sh\n\
+                 account [error=Account expired] rc=13\n",
+            ),
+            ("other", "auth rc=9\n"),
+        ],
+        AuthenticatorBuilder::new(),
+    );
+    let (messages, code) = converse(&auth, "test", "bob", &["123"]);
+    assert_eq!(
+        messages,
+        [
+            Message::Info("Hello".to_owned()),
+            Message::Echo("Token: ".to_owned()),
+            Message::Error("Account expired".to_owned()),
+            Message::ValidationFailed
+        ]
+    );
+    assert_eq!(code, Some(PAM_ACCT_EXPIRED));
+    // the lines after a failure are not executed
+    let (messages, _) = converse(&auth, "test", "bob", &["12"]);
+    assert_eq!(messages.last(), Some(&Message::AuthenticationFailed));
+    let (_, code) = converse(&auth, "unknown", "bob", &[]);
+    assert_eq!(code, Some(PAM_AUTHINFO_UNAVAIL));
+}
+
+#[test]
+fn items() {
+    let auth = setup(
+        "items",
+        &[
+            ("rhost", "auth expect_rhost=10.0.0.1\n"),
+            (
+                "remap",
+                "auth expect_user=bob\nauth user=alice\nauth expect_user=alice\n\
+                 account expect_user=alice\n",
+            ),
+        ],
+        AuthenticatorBuilder::new(),
+    );
+    assert!(!auth.authenticate_sync("rhost", "bob", "").unwrap());
+    assert!(
+        auth.with_remote_host("10.0.0.1")
+            .authenticate_sync("rhost", "bob", "")
+            .unwrap()
+    );
+    assert!(auth.authenticate_sync("remap", "bob", "").unwrap());
+}
+
+#[test]
+fn fail_delay() {
+    let auth = setup(
+        "fail_delay",
+        &[("test", "auth fail_delay=1000000 rc=7\n")],
+        AuthenticatorBuilder::new().workers(1),
+    );
+    let started = Instant::now();
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let auth = auth.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                assert!(!auth.authenticate_sync("test", "bob", "").unwrap());
+                started.elapsed()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap() >= Duration::from_millis(900));
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn bad_scenario() {
+    let auth = setup(
+        "bad_scenario",
+        &[("test", "session rc=0\n")],
+        AuthenticatorBuilder::new(),
+    );
+    assert!(auth.authenticate_sync("test", "bob", "").is_err());
+}
@@ -0,0 +1,315 @@
+// Worker pool tests, run with the libpam stub
+#![cfg(not(feature = "link"))]
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use easypam::{
+    AdmissionPolicy, Authenticator, AuthenticatorBuilder, Conversation, Error, PoolConfig, Priority,
+};
+
+use stub::{setup, setup_with, stub_copy};
+
+mod stub;
+
+// a single worker, busy with the returned conversation (the stub delays the authentication), and a
+// queue of one request
+fn busy_pool(
+    name: &str,
+    admission: AdmissionPolicy,
+    timeout: Duration,
+) -> (Authenticator, Conversation) {
+    let auth = setup(
+        name,
+        &[("test", "auth delay=500\n")],
+        AuthenticatorBuilder::new()
+            .workers(1)
+            .queue_size(1)
+            .admission(admission)
+            .timeout(timeout),
+    );
+    let c = auth.chat_sync("test", "bob").unwrap();
+    (auth, c)
+}
+
+fn queue(auth: &Authenticator) -> JoinHandle<Result<Conversation, Error>> {
+    let auth = auth.clone();
+    let handle = std::thread::spawn(move || auth.chat_sync("test", "bob"));
+    std::thread::sleep(Duration::from_millis(50));
+    handle
+}
+
+#[test]
+fn stuck_start() {
+    let auth = setup(
+        "pool-stuck_start",
+        &[("stuck", "start delay=2000\n"), ("test", "auth\n")],
+        AuthenticatorBuilder::new()
+            .workers(1)
+            .call_timeout(Duration::from_millis(200)),
+    );
+    let started = Instant::now();
+    // the caller gets a failure from the watchdog instead of waiting for the request timeout
+    assert!(matches!(
+        auth.chat_sync("stuck", "bob"),
+        Err(Error::Failed(_))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // the request is processed by the replacement worker
+    assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+}
+
+#[test]
+fn elastic() {
+    let auth = setup(
+        "pool-elastic",
+        &[("test", "auth delay=300\n")],
+        AuthenticatorBuilder::new()
+            .min_workers(1)
+            .max_workers(3)
+            .idle_timeout(Duration::from_millis(200)),
+    );
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let auth = auth.clone();
+            std::thread::spawn(move || auth.authenticate_sync("test", "bob", "").unwrap())
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(auth.status().unwrap().workers, 3);
+    for handle in handles {
+        assert!(handle.join().unwrap());
+    }
+    // the idle workers are retired down to the minimum
+    std::thread::sleep(Duration::from_secs(1));
+    let status = auth.status().unwrap();
+    assert_eq!(status.workers, 1);
+    assert_eq!(status.healthy, 1);
+}
+
+#[test]
+fn admission_reject() {
+    let (auth, _c) = busy_pool(
+        "pool-admission_reject",
+        AdmissionPolicy::Reject,
+        Duration::from_secs(2),
+    );
+    let queued = queue(&auth);
+    let started = Instant::now();
+    assert!(matches!(auth.chat_sync("test", "bob"), Err(Error::Busy)));
+    assert!(started.elapsed() < Duration::from_millis(100));
+    assert!(queued.join().unwrap().is_ok());
+}
+
+#[test]
+fn admission_wait() {
+    let (auth, _c) = busy_pool(
+        "pool-admission_wait",
+        AdmissionPolicy::Wait,
+        Duration::from_millis(300),
+    );
+    let queued = queue(&auth);
+    let started = Instant::now();
+    // the slot is not freed before the timeout
+    assert!(matches!(auth.chat_sync("test", "bob"), Err(Error::Busy)));
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    assert!(matches!(queued.join().unwrap(), Err(Error::Timeout)));
+}
+
+#[test]
+fn admission_shed_oldest() {
+    let (auth, _c) = busy_pool(
+        "pool-admission_shed_oldest",
+        AdmissionPolicy::ShedOldest,
+        Duration::from_secs(2),
+    );
+    let queued = queue(&auth);
+    // the queued request is shed in favour of the new one
+    let newest = queue(&auth);
+    assert!(matches!(queued.join().unwrap(), Err(Error::Busy)));
+    assert!(newest.join().unwrap().is_ok());
+}
+
+#[test]
+fn admission_shed_priority() {
+    let (auth, _c) = busy_pool(
+        "pool-admission_shed_priority",
+        AdmissionPolicy::ShedOldest,
+        Duration::from_secs(2),
+    );
+    let queued = queue(&auth.with_priority(Priority::High));
+    // requests with higher priorities are not shed by the lower ones
+    assert!(matches!(auth.chat_sync("test", "bob"), Err(Error::Busy)));
+    let newest = queue(&auth.with_priority(Priority::High));
+    assert!(matches!(queued.join().unwrap(), Err(Error::Busy)));
+    assert!(newest.join().unwrap().is_ok());
+}
+
+#[test]
+fn abandoned_start() {
+    let auth = setup(
+        "pool-abandoned_start",
+        &[
+            ("ghost", "start delay=300\nauth delay=1000\n"),
+            ("test", "auth\n"),
+        ],
+        AuthenticatorBuilder::new()
+            .workers(1)
+            .timeout(Duration::from_millis(250)),
+    );
+    // the caller leaves while the worker is in pam_start
+    assert!(matches!(
+        auth.chat_sync("ghost", "bob"),
+        Err(Error::Timeout)
+    ));
+    // the worker does not run the stack for nobody and is free for the next request
+    assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+}
+
+#[test]
+fn priorities() {
+    let auth = setup(
+        "pool-priorities",
+        &[("test", "auth delay=100\n")],
+        AuthenticatorBuilder::new().workers(1),
+    );
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut handles = Vec::new();
+    for priority in [
+        Priority::Normal,
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+    ] {
+        let auth = auth.with_priority(priority);
+        let order = order.clone();
+        handles.push(std::thread::spawn(move || {
+            assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+            order.lock().unwrap().push(priority);
+        }));
+        // the first request occupies the worker, the others are queued in order
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(
+        *order.lock().unwrap(),
+        [
+            Priority::Normal,
+            Priority::High,
+            Priority::Normal,
+            Priority::Low
+        ]
+    );
+}
+
+#[test]
+fn reserved_workers() {
+    let auth = setup(
+        "pool-reserved_workers",
+        &[("slow", "auth delay=500\n"), ("test", "auth\n")],
+        AuthenticatorBuilder::new()
+            .min_workers(1)
+            .max_workers(2)
+            .reserved_workers(1)
+            .idle_timeout(Duration::from_millis(200)),
+    );
+    let low = auth.with_priority(Priority::Low);
+    let busy: Vec<_> = (0..3)
+        .map(|_| {
+            let low = low.clone();
+            std::thread::spawn(move || low.authenticate_sync("slow", "bob", "").unwrap())
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(100));
+    // the queued low-priority request does not get a reserved worker
+    let status = auth.status().unwrap();
+    assert_eq!((status.workers, status.queued), (2, 1));
+    // a high-priority request is processed at once by a reserved worker
+    let started = Instant::now();
+    assert!(
+        auth.with_priority(Priority::High)
+            .authenticate_sync("test", "bob", "")
+            .unwrap()
+    );
+    assert!(started.elapsed() < Duration::from_millis(300));
+    for handle in busy {
+        assert!(handle.join().unwrap());
+    }
+    // the reserved worker is gone, the regular ones are retired down to the minimum
+    std::thread::sleep(Duration::from_secs(1));
+    let status = auth.status().unwrap();
+    assert_eq!((status.workers, status.healthy), (1, 1));
+}
+
+#[test]
+fn services() {
+    let auth = setup(
+        "pool-services",
+        &[("slow", "auth delay=500\n"), ("sshd", "auth\n")],
+        AuthenticatorBuilder::new()
+            .workers(1)
+            .service("sshd", PoolConfig::new().workers(2)),
+    );
+    assert_eq!(auth.service_status("sshd").unwrap().workers, 2);
+    assert!(auth.service_status("slow").is_none());
+    // the default pool is busy
+    let _c = auth.chat_sync("slow", "bob").unwrap();
+    let started = Instant::now();
+    assert!(auth.authenticate_sync("sshd", "bob", "").unwrap());
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert_eq!(auth.status().unwrap().busy, 1);
+    assert_eq!(auth.service_status("sshd").unwrap().busy, 0);
+}
+
+#[test]
+fn restart() {
+    let library = stub_copy("pool-restart");
+    let auth = setup_with(
+        "pool-restart",
+        &[("test", "auth\n")],
+        &library,
+        AuthenticatorBuilder::new()
+            .min_workers(0)
+            .max_workers(1)
+            .idle_timeout(Duration::from_millis(100))
+            .timeout(Duration::from_millis(500)),
+    );
+    assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+    // the idle worker is retired, so the library is unloaded
+    let started = Instant::now();
+    while auth.status().unwrap().workers > 0 {
+        assert!(started.elapsed() < Duration::from_secs(2));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let disabled = library.with_extension("disabled");
+    std::fs::rename(&library, &disabled).unwrap();
+    // the new worker fails to start, the queued request times out
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", ""),
+        Err(Error::NoHealthyWorkers(_))
+    ));
+    let status = auth.status().unwrap();
+    assert_eq!(status.healthy, 0);
+    assert_eq!(status.restarts_pending, 1);
+    assert!(status.last_error.is_some());
+    // further requests are rejected without waiting
+    let started = Instant::now();
+    assert!(matches!(
+        auth.authenticate_sync("test", "bob", ""),
+        Err(Error::NoHealthyWorkers(_))
+    ));
+    assert!(started.elapsed() < Duration::from_millis(100));
+    // the failed worker is restarted with a backoff as soon as the library is back
+    std::fs::rename(&disabled, &library).unwrap();
+    let started = Instant::now();
+    while auth.status().unwrap().healthy == 0 {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(auth.status().unwrap().restarts_pending, 0);
+    assert!(auth.authenticate_sync("test", "bob", "").unwrap());
+}
@@ -0,0 +1,107 @@
+use easypam::{Message, PromptKind};
+
+#[test]
+fn classify() {
+    let prompts = [
+        // pam_unix, pam_pwquality, pam_get_authtok
+        ("Password: ", PromptKind::Password),
+        ("Current password: ", PromptKind::CurrentPassword),
+        ("(current) UNIX password: ", PromptKind::CurrentPassword),
+        ("New password: ", PromptKind::NewPassword),
+        ("Enter new UNIX password: ", PromptKind::NewPassword),
+        ("Retype new password: ", PromptKind::RetypeNewPassword),
+        ("Retype new UNIX password: ", PromptKind::RetypeNewPassword),
+        ("login: ", PromptKind::Username),
+        ("Username: ", PromptKind::Username),
+        // pam_sss, pam_krb5
+        ("Current Password: ", PromptKind::CurrentPassword),
+        ("Reenter new Password: ", PromptKind::RetypeNewPassword),
+        ("First Factor: ", PromptKind::Password),
+        ("Second Factor: ", PromptKind::Otp),
+        ("Password for andrew@EXAMPLE.COM: ", PromptKind::Password),
+        ("Password for newman@EXAMPLE.COM: ", PromptKind::Password),
+        ("Password for oldham@EXAMPLE.COM: ", PromptKind::Password),
+        ("Password for tokenadmin: ", PromptKind::Password),
+        ("New password for loginov: ", PromptKind::NewPassword),
+        ("Current password for newman: ", PromptKind::CurrentPassword),
+        ("Passwort für newman: ", PromptKind::Password),
+        ("newman@EXAMPLE.COM's password: ", PromptKind::Password),
+        // OTP modules
+        ("Verification code: ", PromptKind::Otp),
+        ("One-time password (OATH) for `bob': ", PromptKind::Otp),
+        ("YubiKey for `bob': ", PromptKind::Otp),
+        ("Enter PASSCODE: ", PromptKind::Otp),
+        // translations
+        ("Passwort: ", PromptKind::Password),
+        ("Neues Passwort: ", PromptKind::NewPassword),
+        (
+            "Neues Passwort wiederholen: ",
+            PromptKind::RetypeNewPassword,
+        ),
+        ("Aktuelles Passwort: ", PromptKind::CurrentPassword),
+        ("Mot de passe : ", PromptKind::Password),
+        ("Nouveau mot de passe : ", PromptKind::NewPassword),
+        (
+            "Retapez le nouveau mot de passe : ",
+            PromptKind::RetypeNewPassword,
+        ),
+        ("Mot de passe actuel : ", PromptKind::CurrentPassword),
+        ("Contraseña: ", PromptKind::Password),
+        ("Nueva contraseña: ", PromptKind::NewPassword),
+        (
+            "Vuelva a escribir la nueva contraseña: ",
+            PromptKind::RetypeNewPassword,
+        ),
+        ("Contraseña actual: ", PromptKind::CurrentPassword),
+        ("Nova senha: ", PromptKind::NewPassword),
+        ("Redigite a nova senha: ", PromptKind::RetypeNewPassword),
+        ("Пароль: ", PromptKind::Password),
+        ("Новый пароль: ", PromptKind::NewPassword),
+        (
+            "Повторите ввод нового пароля: ",
+            PromptKind::RetypeNewPassword,
+        ),
+        ("Текущий пароль: ", PromptKind::CurrentPassword),
+        ("Новий пароль: ", PromptKind::NewPassword),
+        ("Поточний пароль: ", PromptKind::CurrentPassword),
+        ("Hasło: ", PromptKind::Password),
+        ("Nowe hasło: ", PromptKind::NewPassword),
+        ("Nieuw wachtwoord herhalen: ", PromptKind::RetypeNewPassword),
+        ("Ange nytt lösenord igen: ", PromptKind::RetypeNewPassword),
+        ("パスワード: ", PromptKind::Password),
+        ("新しいパスワード: ", PromptKind::NewPassword),
+        (
+            "新しいパスワードを再入力してください: ",
+            PromptKind::RetypeNewPassword,
+        ),
+        ("密码：", PromptKind::Password),
+        ("新的密码：", PromptKind::NewPassword),
+        ("重新输入新的密码：", PromptKind::RetypeNewPassword),
+        ("当前密码：", PromptKind::CurrentPassword),
+        ("새 암호:", PromptKind::NewPassword),
+        ("Benutzername: ", PromptKind::Username),
+        // unknown
+        ("Press Enter to continue", PromptKind::Unknown),
+        ("Passcode or option (1-3): ", PromptKind::Otp),
+        ("Favourite colour: ", PromptKind::Unknown),
+    ];
+    for (prompt, kind) in prompts {
+        assert_eq!(PromptKind::classify(prompt), kind, "{}", prompt);
+    }
+}
+
+#[test]
+fn messages() {
+    assert_eq!(
+        Message::NoEcho("Password: ".to_owned()).prompt_kind(),
+        Some(PromptKind::Password)
+    );
+    assert_eq!(
+        Message::Echo("login: ".to_owned()).prompt_kind(),
+        Some(PromptKind::Username)
+    );
+    assert_eq!(Message::Info("Password: ".to_owned()).prompt_kind(), None);
+    assert_eq!(Message::Authenticated.prompt_kind(), None);
+    assert!(PromptKind::RetypeNewPassword.is_password());
+    assert!(!PromptKind::Otp.is_password());
+}
@@ -0,0 +1,105 @@
+// Stream and Sink halves of conversations with the mock backend
+#![cfg(all(feature = "async", feature = "testing"))]
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use easypam::{
+    Message, PromptKind,
+    testing::{MockBackend, MockUser},
+};
+use futures::{SinkExt, StreamExt};
+
+const PAM_AUTH_ERR: i32 = 7;
+
+fn authenticator() -> easypam::Authenticator {
+    MockBackend::new()
+        .user(
+            "bob",
+            MockUser::new()
+                .info("Welcome")
+                .echo("OTP: ", "123")
+                .no_echo("Password: ", "secret"),
+        )
+        .authenticator()
+}
+
+#[tokio::test]
+async fn split() {
+    let auth = authenticator();
+    let (mut messages, mut answers) = auth.chat("login", "bob").await.unwrap().split();
+    let mut received = Vec::new();
+    while let Some(msg) = messages.next().await {
+        match msg.prompt_kind() {
+            Some(PromptKind::Otp) => answers.send("123".to_owned()).await.unwrap(),
+            Some(PromptKind::Password) => answers.send("secret".to_owned()).await.unwrap(),
+            _ => {}
+        }
+        received.push(msg);
+    }
+    assert_eq!(
+        received,
+        [
+            Message::Info("Welcome".to_owned()),
+            Message::Echo("OTP: ".to_owned()),
+            Message::NoEcho("Password: ".to_owned()),
+            Message::Authenticated
+        ]
+    );
+    // the stream is finished after the outcome
+    assert_eq!(messages.next().await, None);
+    answers.close().await.unwrap();
+    assert!(answers.send(String::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn forward() {
+    let auth = authenticator();
+    let (messages, answers) = auth.chat("login", "bob").await.unwrap().split();
+    let outcome = Arc::new(Mutex::new(None));
+    let o = outcome.clone();
+    // the prompts are answered by a "client" stream, e.g. a websocket
+    messages
+        .inspect(move |msg| {
+            if matches!(msg, Message::Authenticated | Message::AuthenticationFailed) {
+                o.lock().unwrap().replace(msg.clone());
+            }
+        })
+        .filter_map(|msg| async move {
+            match msg {
+                Message::Echo(_) => Some(Ok("123".to_owned())),
+                Message::NoEcho(_) => Some(Ok("wrong".to_owned())),
+                _ => None,
+            }
+        })
+        .forward(answers)
+        .await
+        .unwrap();
+    assert_eq!(
+        *outcome.lock().unwrap(),
+        Some(Message::AuthenticationFailed)
+    );
+}
+
+#[tokio::test]
+async fn select() {
+    let auth = authenticator();
+    let (mut messages, mut answers) = auth.chat("login", "bob").await.unwrap().split();
+    let timeout = tokio::time::sleep(Duration::from_secs(5));
+    tokio::pin!(timeout);
+    let mut outcome = None;
+    while outcome.is_none() {
+        tokio::select! {
+            Some(msg) = messages.next() => match msg {
+                Message::Echo(_) => answers.send("123".to_owned()).await.unwrap(),
+                Message::NoEcho(_) => answers.send("wrong".to_owned()).await.unwrap(),
+                Message::Info(_) | Message::Error(_) => {}
+                msg => outcome = Some(msg),
+            },
+            () = &mut timeout => panic!("timed out"),
+        }
+    }
+    assert_eq!(outcome, Some(Message::AuthenticationFailed));
+    assert_eq!(messages.failure_code(), Some(PAM_AUTH_ERR));
+}
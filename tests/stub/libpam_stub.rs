@@ -0,0 +1,431 @@
+//! A libpam test double, which can be loaded by easypam instead of `libpam.so.0` on systems
+//! without PAM, e.g. with `AuthenticatorBuilder::library("/path/to/libpam_stub.so")`.
+//!
+//! Conversations are driven by scenario files, a file per service, read from the directory passed
+//! to `pam_start_confdir` or set with `EASYPAM_STUB_CONFDIR` environment variable (`other` is used
+//! if there is no file for a service). Each line of a scenario contains a stage (`auth` or
+//! `account`) and the arguments of the bundled test module (see `tests/module`), e.g.:
+//!
+//! ```text
+//! # comments and empty lines are ignored
+//! auth [info=Welcome] [noecho=Password: ] expect=secret
+//! auth expect_rhost=10.0.0.1 env=HOME=/home/bob
+//! account rc=13
+//! ```
+//!
+//! The lines of a stage are executed in order until the first failure, stages without lines
+//! succeed. `start delay=MS` lines make `pam_start` itself slow, e.g. to test hung workers. Fail
+//! delays are not randomized and are passed to `PAM_FAIL_DELAY` function if set.
+//!
+//! The stub is for tests only and is not built with the crate, see `tests/stub/mod.rs` for the
+//! build command.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[path = "../module/pam_easypam_test.rs"]
+mod module;
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_SYSTEM_ERR: c_int = 4;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_BAD_ITEM: c_int = 29;
+
+const PAM_SERVICE: c_int = 1;
+const PAM_USER: c_int = 2;
+const PAM_CONV: c_int = 5;
+const PAM_USER_PROMPT: c_int = 9;
+const PAM_FAIL_DELAY: c_int = 10;
+
+const CONFDIR_ENV: &str = "EASYPAM_STUB_CONFDIR";
+
+type ConvFn = extern "C" fn(c_int, *mut *const c_void, *mut *mut c_void, *mut c_void) -> c_int;
+type DelayFn = extern "C" fn(c_int, c_uint, *mut c_void);
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<ConvFn>,
+    appdata_ptr: *mut c_void,
+}
+
+unsafe extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn strdup(s: *const c_char) -> *mut c_char;
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Stage {
+    Start,
+    Auth,
+    Account,
+}
+
+struct Handle {
+    scenario: Vec<(Stage, Vec<CString>)>,
+    // string items, PAM_SERVICE, PAM_USER, PAM_RHOST etc.
+    items: Vec<(c_int, CString)>,
+    conv: PamConv,
+    delay_fn: *const c_void,
+    // requested by the modules with pam_fail_delay, microseconds
+    fail_delay: Option<c_uint>,
+    // NAME=VALUE
+    env: Vec<CString>,
+}
+
+impl Handle {
+    fn item(&self, item_type: c_int) -> Option<&CString> {
+        self.items
+            .iter()
+            .find_map(|(t, v)| (*t == item_type).then_some(v))
+    }
+    fn set_item(&mut self, item_type: c_int, value: Option<CString>) {
+        self.items.retain(|(t, _)| *t != item_type);
+        if let Some(value) = value {
+            self.items.push((item_type, value));
+        }
+    }
+    fn env_position(&self, name: &[u8]) -> Option<usize> {
+        self.env.iter().position(|v| {
+            v.as_bytes()
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.first() == Some(&b'='))
+        })
+    }
+}
+
+// splits a line into arguments, the ones with spaces are put into brackets
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        }
+        let mut arg = String::new();
+        if ch == '[' {
+            for ch in chars.by_ref() {
+                if ch == ']' {
+                    break;
+                }
+                arg.push(ch);
+            }
+        } else {
+            arg.push(ch);
+            while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+                arg.push(ch);
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+fn load_scenario(confdir: &Path, service: &str) -> Option<Vec<(Stage, Vec<CString>)>> {
+    let contents = std::fs::read_to_string(confdir.join(service))
+        .or_else(|_| std::fs::read_to_string(confdir.join("other")))
+        .ok()?;
+    let mut scenario = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (stage, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let stage = match stage {
+            "start" => Stage::Start,
+            "auth" => Stage::Auth,
+            "account" => Stage::Account,
+            _ => return None,
+        };
+        let args = split_args(args)
+            .into_iter()
+            .map(CString::new)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        scenario.push((stage, args));
+    }
+    Some(scenario)
+}
+
+unsafe fn start(
+    service_name: *const c_char,
+    user: *const c_char,
+    pam_conversation: *const c_void,
+    confdir: Option<PathBuf>,
+    pamh: *mut *mut c_void,
+) -> c_int {
+    if service_name.is_null() || pam_conversation.is_null() || pamh.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    let service = unsafe { CStr::from_ptr(service_name) };
+    let Some(confdir) = confdir.or_else(|| std::env::var_os(CONFDIR_ENV).map(PathBuf::from)) else {
+        return PAM_SYSTEM_ERR;
+    };
+    let Some(scenario) = load_scenario(&confdir, &service.to_string_lossy()) else {
+        return PAM_SYSTEM_ERR;
+    };
+    for (_, args) in scenario.iter().filter(|(stage, _)| *stage == Stage::Start) {
+        for arg in args {
+            if let Some(ms) = arg.to_str().ok().and_then(|a| a.strip_prefix("delay=")) {
+                std::thread::sleep(Duration::from_millis(ms.parse().unwrap_or_default()));
+            }
+        }
+    }
+    let conv = unsafe { &*pam_conversation.cast::<PamConv>() };
+    let mut handle = Handle {
+        scenario,
+        items: vec![(PAM_SERVICE, service.to_owned())],
+        conv: PamConv {
+            conv: conv.conv,
+            appdata_ptr: conv.appdata_ptr,
+        },
+        delay_fn: std::ptr::null(),
+        fail_delay: None,
+        env: Vec::new(),
+    };
+    if !user.is_null() {
+        handle.set_item(PAM_USER, Some(unsafe { CStr::from_ptr(user) }.to_owned()));
+    }
+    unsafe { *pamh = Box::into_raw(Box::new(handle)).cast() };
+    PAM_SUCCESS
+}
+
+// runs the stage lines until the first failure
+unsafe fn run_stage(pamh: *mut c_void, stage: Stage) -> c_int {
+    if pamh.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    // the handle is not borrowed while the module runs, as it calls the item functions
+    let lines: Vec<Vec<CString>> = unsafe { &*pamh.cast::<Handle>() }
+        .scenario
+        .iter()
+        .filter(|(s, _)| *s == stage)
+        .map(|(_, args)| args.clone())
+        .collect();
+    for args in lines {
+        let argv: Vec<*const c_char> = args.iter().map(|a| a.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap_or_default();
+        let rc = unsafe {
+            match stage {
+                Stage::Auth => module::pam_sm_authenticate(pamh, 0, argc, argv.as_ptr()),
+                Stage::Account => module::pam_sm_acct_mgmt(pamh, 0, argc, argv.as_ptr()),
+                Stage::Start => PAM_SUCCESS,
+            }
+        };
+        if rc != PAM_SUCCESS {
+            return rc;
+        }
+    }
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_start(
+    service_name: *const c_char,
+    user: *const c_char,
+    pam_conversation: *const c_void,
+    pamh: *mut *mut c_void,
+) -> c_int {
+    unsafe { start(service_name, user, pam_conversation, None, pamh) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_start_confdir(
+    service_name: *const c_char,
+    user: *const c_char,
+    pam_conversation: *const c_void,
+    confdir: *const c_char,
+    pamh: *mut *mut c_void,
+) -> c_int {
+    let confdir = (!confdir.is_null()).then(|| {
+        PathBuf::from(
+            unsafe { CStr::from_ptr(confdir) }
+                .to_string_lossy()
+                .as_ref(),
+        )
+    });
+    unsafe { start(service_name, user, pam_conversation, confdir, pamh) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_end(pamh: *mut c_void, _pam_status: c_int) -> c_int {
+    if pamh.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    drop(unsafe { Box::from_raw(pamh.cast::<Handle>()) });
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_authenticate(pamh: *mut c_void, _flags: c_int) -> c_int {
+    let rc = unsafe { run_stage(pamh, Stage::Auth) };
+    if pamh.is_null() {
+        return rc;
+    }
+    let handle = unsafe { &mut *pamh.cast::<Handle>() };
+    if let Some(usec) = handle.fail_delay.take() {
+        if handle.delay_fn.is_null() {
+            if rc != PAM_SUCCESS {
+                std::thread::sleep(Duration::from_micros(usec.into()));
+            }
+        } else {
+            let delay_fn =
+                unsafe { std::mem::transmute::<*const c_void, DelayFn>(handle.delay_fn) };
+            delay_fn(rc, usec, handle.conv.appdata_ptr);
+        }
+    }
+    rc
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_acct_mgmt(pamh: *mut c_void, _flags: c_int) -> c_int {
+    unsafe { run_stage(pamh, Stage::Account) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_setcred(_pamh: *mut c_void, _flags: c_int) -> c_int {
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_fail_delay(pamh: *mut c_void, usec: c_uint) -> c_int {
+    if pamh.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    let handle = unsafe { &mut *pamh.cast::<Handle>() };
+    handle.fail_delay = Some(handle.fail_delay.map_or(usec, |d| d.max(usec)));
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_set_item(
+    pamh: *mut c_void,
+    item_type: c_int,
+    item: *const c_void,
+) -> c_int {
+    if pamh.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    let handle = unsafe { &mut *pamh.cast::<Handle>() };
+    match item_type {
+        PAM_CONV => {
+            if item.is_null() {
+                return PAM_BAD_ITEM;
+            }
+            let conv = unsafe { &*item.cast::<PamConv>() };
+            handle.conv = PamConv {
+                conv: conv.conv,
+                appdata_ptr: conv.appdata_ptr,
+            };
+        }
+        PAM_FAIL_DELAY => handle.delay_fn = item,
+        PAM_SERVICE..=PAM_USER_PROMPT => {
+            let value =
+                (!item.is_null()).then(|| unsafe { CStr::from_ptr(item.cast()) }.to_owned());
+            handle.set_item(item_type, value);
+        }
+        _ => return PAM_BAD_ITEM,
+    }
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_get_item(
+    pamh: *const c_void,
+    item_type: c_int,
+    item: *mut *const c_void,
+) -> c_int {
+    if pamh.is_null() || item.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    let handle = unsafe { &*pamh.cast::<Handle>() };
+    let value = match item_type {
+        PAM_CONV => (&raw const handle.conv).cast(),
+        PAM_FAIL_DELAY => handle.delay_fn,
+        PAM_SERVICE..=PAM_USER_PROMPT => handle
+            .item(item_type)
+            .map_or(std::ptr::null(), |v| v.as_ptr().cast()),
+        _ => return PAM_BAD_ITEM,
+    };
+    unsafe { *item = value };
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_putenv(pamh: *mut c_void, name_value: *const c_char) -> c_int {
+    if pamh.is_null() || name_value.is_null() {
+        return PAM_SYSTEM_ERR;
+    }
+    let handle = unsafe { &mut *pamh.cast::<Handle>() };
+    let name_value = unsafe { CStr::from_ptr(name_value) };
+    let bytes = name_value.to_bytes();
+    // NAME deletes the variable
+    let name = bytes.split(|b| *b == b'=').next().unwrap_or_default();
+    if name.is_empty() {
+        return PAM_BAD_ITEM;
+    }
+    let pos = handle.env_position(name);
+    if bytes.len() == name.len() {
+        let Some(pos) = pos else {
+            return PAM_BAD_ITEM;
+        };
+        handle.env.remove(pos);
+    } else if let Some(pos) = pos {
+        handle.env[pos] = name_value.to_owned();
+    } else {
+        handle.env.push(name_value.to_owned());
+    }
+    PAM_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_getenv(pamh: *mut c_void, name: *const c_char) -> *const c_char {
+    if pamh.is_null() || name.is_null() {
+        return std::ptr::null();
+    }
+    let handle = unsafe { &*pamh.cast::<Handle>() };
+    let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+    handle
+        .env_position(name)
+        .map_or(std::ptr::null(), |pos| unsafe {
+            handle.env[pos].as_ptr().add(name.len() + 1)
+        })
+}
+
+// the list and the strings are allocated with malloc and must be freed by the caller
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_getenvlist(pamh: *mut c_void) -> *mut *mut c_char {
+    if pamh.is_null() {
+        return std::ptr::null_mut();
+    }
+    let handle = unsafe { &*pamh.cast::<Handle>() };
+    let list: *mut *mut c_char =
+        unsafe { malloc((handle.env.len() + 1) * size_of::<*mut c_char>()) }.cast();
+    if list.is_null() {
+        return list;
+    }
+    for (i, name_value) in handle.env.iter().enumerate() {
+        unsafe { *list.add(i) = strdup(name_value.as_ptr()) };
+    }
+    unsafe { *list.add(handle.env.len()) = std::ptr::null_mut() };
+    list
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pam_strerror(_pamh: *mut c_void, errnum: c_int) -> *const c_char {
+    match errnum {
+        PAM_SUCCESS => c"Success",
+        PAM_SYSTEM_ERR => c"System error",
+        PAM_BUF_ERR => c"Memory buffer error",
+        7 => c"Authentication failure",
+        9 => c"Authentication service cannot retrieve authentication info",
+        10 => c"User not known to the underlying authentication module",
+        13 => c"User account has expired",
+        19 => c"Conversation error",
+        PAM_BAD_ITEM => c"Bad item passed to pam_*_item()",
+        _ => c"Unknown PAM error",
+    }
+    .as_ptr()
+}
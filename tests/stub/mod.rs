@@ -0,0 +1,104 @@
+// Helpers to run easypam with the libpam stub, shared by the tests
+#![allow(dead_code)]
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use easypam::{Authenticator, AuthenticatorBuilder, Message};
+
+pub fn stub() -> &'static Path {
+    static STUB: OnceLock<PathBuf> = OnceLock::new();
+    STUB.get_or_init(|| {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libpam_easypam_stub.so");
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/stub/libpam_stub.rs");
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+        let output = Command::new(rustc)
+            .args([
+                "--edition",
+                "2024",
+                "--crate-type",
+                "cdylib",
+                "-C",
+                "opt-level=1",
+            ])
+            // the module calls must not be resolved to another libpam loaded into the process
+            .args(["-C", "link-arg=-Wl,-Bsymbolic", "-o"])
+            .arg(&out)
+            .arg(&src)
+            .output()
+            .expect("unable to run rustc");
+        assert!(
+            output.status.success(),
+            "unable to build the libpam stub: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        out
+    })
+}
+
+// a private copy of the stub, which can be removed to make the workers fail
+pub fn stub_copy(name: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("libpam_stub-{}.so", name));
+    std::fs::copy(stub(), &path).unwrap();
+    path
+}
+
+// services: name, scenario
+pub fn setup(
+    name: &str,
+    services: &[(&str, &str)],
+    builder: AuthenticatorBuilder,
+) -> Authenticator {
+    setup_with(name, services, stub(), builder)
+}
+
+pub fn setup_with(
+    name: &str,
+    services: &[(&str, &str)],
+    library: &Path,
+    builder: AuthenticatorBuilder,
+) -> Authenticator {
+    let confdir = confdir(name);
+    std::fs::create_dir_all(&confdir).unwrap();
+    for (service, scenario) in services {
+        std::fs::write(confdir.join(service), scenario).unwrap();
+    }
+    builder.library(library).confdir(confdir).build().unwrap()
+}
+
+fn confdir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("stub.d-{}", name))
+}
+
+// scenarios are read on every pam_start, so the service behaviour can be changed on the fly
+pub fn rewrite(name: &str, service: &str, scenario: &str) {
+    std::fs::write(confdir(name).join(service), scenario).unwrap();
+}
+
+pub fn converse(
+    auth: &Authenticator,
+    service: &str,
+    login: &str,
+    answers: &[&str],
+) -> (Vec<Message>, Option<i32>) {
+    let c = auth.chat_sync(service, login).unwrap();
+    let mut answers = answers.iter();
+    let mut messages = Vec::new();
+    while let Ok(msg) = c.rx().recv_blocking() {
+        messages.push(msg.clone());
+        match msg {
+            Message::Echo(_) | Message::NoEcho(_) => {
+                c.tx()
+                    .send_blocking((*answers.next().unwrap()).to_owned())
+                    .unwrap();
+            }
+            Message::Info(_) | Message::Error(_) => {}
+            Message::Authenticated | Message::AuthenticationFailed | Message::ValidationFailed => {
+                break;
+            }
+        }
+    }
+    (messages, c.failure_code())
+}
@@ -0,0 +1,161 @@
+// Terminal conversation tests, the conversations are run by the test binary itself in a child
+// process with a pseudo-terminal as the controlling one
+#![cfg(all(feature = "terminal", feature = "testing"))]
+use std::{
+    fs::File,
+    io::{Read, Write},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use easypam::{Error, testing::MockBackend, testing::MockUser};
+
+const CHILD_ENV: &str = "EASYPAM_TERMINAL_TEST";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn is_child(test: &str) -> bool {
+    std::env::var(CHILD_ENV).is_ok_and(|v| v == test)
+}
+
+// runs the test in a child process, returns the pseudo-terminal master
+fn spawn(test: &str) -> (File, Child) {
+    let (mut master, mut slave) = (0, 0);
+    let rc = unsafe {
+        libc::openpty(
+            &raw mut master,
+            &raw mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(rc, 0, "{}", std::io::Error::last_os_error());
+    let master = unsafe { File::from_raw_fd(master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    let mut cmd = Command::new(std::env::current_exe().unwrap());
+    cmd.args([test, "--exact", "--test-threads=1"])
+        .env(CHILD_ENV, test)
+        .stdin(Stdio::from(slave))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    (master, cmd.spawn().unwrap())
+}
+
+// reads the terminal output until the text appears or the child closes the terminal
+fn read_until(mut master: &File, text: &str) -> String {
+    let started = Instant::now();
+    let mut output = Vec::new();
+    while !String::from_utf8_lossy(&output).contains(text) {
+        assert!(started.elapsed() < TIMEOUT, "{:?}", output);
+        let mut pollfd = libc::pollfd {
+            fd: master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&raw mut pollfd, 1, 100) } <= 0 {
+            continue;
+        }
+        let mut buf = [0u8; 256];
+        match master.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn echo(fd: i32) -> bool {
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+    assert_eq!(unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) }, 0);
+    unsafe { termios.assume_init() }.c_lflag & libc::ECHO != 0
+}
+
+fn wait_echo_off(master: &File) {
+    let started = Instant::now();
+    while echo(master.as_raw_fd()) {
+        assert!(started.elapsed() < TIMEOUT);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn wait(mut child: Child) {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            assert!(status.success(), "{}", status);
+            return;
+        }
+        if started.elapsed() > TIMEOUT {
+            child.kill().ok();
+            panic!("the child has not finished");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn tty_echo() -> bool {
+    echo(File::open("/dev/tty").unwrap().as_raw_fd())
+}
+
+#[test]
+fn echo_off() {
+    if is_child("echo_off") {
+        let auth = MockBackend::new()
+            .user(
+                "bob",
+                MockUser::new().info("Welcome").no_echo("Password: ", "xxx"),
+            )
+            .authenticator();
+        let c = auth.chat_sync("test", "bob").unwrap();
+        assert!(c.run_terminal().unwrap());
+        assert!(tty_echo());
+        return;
+    }
+    let (mut master, child) = spawn("echo_off");
+    assert!(read_until(&master, "Password: ").contains("Welcome"));
+    wait_echo_off(&master);
+    master.write_all(b"xxx\n").unwrap();
+    wait(child);
+    // the password is not echoed, the new line is printed instead
+    assert!(!read_until(&master, "xxx").contains("xxx"));
+}
+
+#[test]
+fn interrupt() {
+    if is_child("interrupt") {
+        let auth = MockBackend::new()
+            .user("bob", MockUser::password("xxx"))
+            .authenticator();
+        let c = auth.chat_sync("test", "bob").unwrap();
+        assert!(matches!(c.run_terminal(), Err(Error::Cancelled)));
+        assert!(tty_echo());
+        // the default SIGINT handler is restored
+        let mut action = MaybeUninit::<libc::sigaction>::uninit();
+        assert_eq!(
+            unsafe { libc::sigaction(libc::SIGINT, std::ptr::null(), action.as_mut_ptr()) },
+            0
+        );
+        assert_eq!(unsafe { action.assume_init() }.sa_sigaction, libc::SIG_DFL);
+        return;
+    }
+    let (mut master, child) = spawn("interrupt");
+    read_until(&master, "Password: ");
+    wait_echo_off(&master);
+    // Ctrl-C
+    master.write_all(&[3]).unwrap();
+    wait(child);
+}
@@ -0,0 +1,140 @@
+// Recording with the mock backend and replaying the transcripts
+#![cfg(all(feature = "transcript", feature = "testing"))]
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use easypam::{
+    AuthenticatorBuilder, Message,
+    testing::{MockBackend, MockUser},
+    transcript::{Event, ReplayBackend, Transcript},
+};
+
+const PAM_CONV_ERR: i32 = 19;
+const PAM_ACCT_EXPIRED: i32 = 13;
+
+fn record(user: MockUser, answers: &[&str]) -> Transcript {
+    let transcripts = Arc::new(Mutex::new(Vec::new()));
+    let t = transcripts.clone();
+    let auth = MockBackend::new()
+        .user("bob", user)
+        .build(AuthenticatorBuilder::new().record(move |transcript| {
+            t.lock().unwrap().push(transcript);
+        }))
+        .unwrap();
+    let c = auth.chat_sync("login", "bob").unwrap();
+    let mut answers = answers.iter();
+    while let Ok(msg) = c.rx().recv_blocking() {
+        match msg {
+            Message::Echo(_) | Message::NoEcho(_) => {
+                c.tx()
+                    .send_blocking((*answers.next().unwrap()).to_owned())
+                    .unwrap();
+            }
+            Message::Info(_) | Message::Error(_) => {}
+            Message::Authenticated | Message::AuthenticationFailed | Message::ValidationFailed => {
+                break;
+            }
+        }
+    }
+    // the handler is called by the recorder thread
+    let started = Instant::now();
+    loop {
+        if let Some(transcript) = transcripts.lock().unwrap().pop() {
+            return transcript;
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn messages(transcript: &Transcript) -> Vec<Message> {
+    transcript
+        .events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Message { message, .. } => Some(message.clone()),
+            Event::Answer { .. } => None,
+        })
+        .collect()
+}
+
+#[test]
+fn record_replay() {
+    let user = MockUser::new()
+        .info("Welcome")
+        .no_echo("Password: ", "secret")
+        .delay(Duration::from_millis(200))
+        .echo("OTP: ", "123456")
+        .error("Your password has expired")
+        .validation_failed();
+    let transcript = record(user, &["secret", "123456"]);
+    assert_eq!(transcript.service, "login");
+    assert_eq!(transcript.login, "bob");
+    assert_eq!(transcript.failure_code, Some(PAM_ACCT_EXPIRED));
+    assert_eq!(transcript.outcome(), Some(&Message::ValidationFailed));
+    let expected = [
+        Message::Info("Welcome".to_owned()),
+        Message::NoEcho("Password: ".to_owned()),
+        Message::Echo("OTP: ".to_owned()),
+        Message::Error("Your password has expired".to_owned()),
+        Message::ValidationFailed,
+    ];
+    assert_eq!(messages(&transcript), expected);
+    assert_eq!(
+        transcript
+            .events
+            .iter()
+            .filter(|e| matches!(e, Event::Answer { .. }))
+            .count(),
+        2
+    );
+    assert!(transcript.events[3].elapsed() >= Duration::from_millis(200));
+    // the answers are redacted
+    let serialized = serde_json::to_string(&transcript).unwrap();
+    assert!(!serialized.contains("secret"));
+    assert!(!serialized.contains("123456"));
+    let transcript: Transcript = serde_json::from_str(&serialized).unwrap();
+    // any answers are accepted by the replay
+    let auth = ReplayBackend::new(transcript.clone()).authenticator();
+    let c = auth.chat_sync("other", "alice").unwrap();
+    let mut replayed = Vec::new();
+    while let Ok(msg) = c.rx().recv_blocking() {
+        replayed.push(msg.clone());
+        match msg {
+            Message::Echo(_) | Message::NoEcho(_) => c.tx().send_blocking(String::new()).unwrap(),
+            Message::Info(_) | Message::Error(_) => {}
+            _ => break,
+        }
+    }
+    assert_eq!(replayed, expected);
+    assert_eq!(c.failure_code(), Some(PAM_ACCT_EXPIRED));
+    // timing
+    let auth = ReplayBackend::new(transcript).timing(true).authenticator();
+    let started = Instant::now();
+    assert!(!auth.authenticate_sync("login", "bob", "").unwrap());
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn replay_timeout() {
+    let transcript = record(MockUser::password("secret"), &["secret"]);
+    assert_eq!(transcript.outcome(), Some(&Message::Authenticated));
+    assert_eq!(transcript.failure_code, None);
+    let auth = ReplayBackend::new(transcript.clone()).authenticator();
+    assert!(auth.authenticate_sync("login", "bob", "xxx").unwrap());
+    let auth = ReplayBackend::new(transcript)
+        .chat_timeout(Duration::from_millis(100))
+        .authenticator();
+    let c = auth.chat_sync("login", "bob").unwrap();
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::NoEcho("Password: ".to_owned())
+    );
+    assert_eq!(
+        c.rx().recv_blocking().unwrap(),
+        Message::AuthenticationFailed
+    );
+    assert_eq!(c.failure_code(), Some(PAM_CONV_ERR));
+}